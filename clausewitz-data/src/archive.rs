@@ -0,0 +1,153 @@
+//! Zip archives, the way mods are packaged when they're distributed as a single file.
+
+use std::path::Path;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::fs::File;
+use std::io::{Read, Write, Error, ErrorKind};
+use zip::{ZipArchive, ZipWriter, CompressionMethod};
+use zip::write::FileOptions;
+use vfs::{FileSystem, MemoryFileSystem};
+
+/// The files inside a zip archive. Files are decompressed when they're read, archives can't be
+/// written to through this.
+pub struct ZipFileSystem {
+    archive: Mutex<ZipArchive<File>>,
+    /// The files by their path with forward slashes, to the name of their entry in the archive.
+    files: BTreeMap<String, String>,
+}
+
+impl ZipFileSystem {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut archive = try!(ZipArchive::new(try!(File::open(path))));
+
+        // Keep track of what's in the archive so we don't need to lock it to look things up,
+        // archives made on Windows can use backslashes in their entry names
+        let mut files = BTreeMap::new();
+        for i in 0..archive.len() {
+            let file = try!(archive.by_index(i));
+            let path = file.name().replace('\\', "/");
+            if !path.ends_with('/') {
+                files.insert(path, file.name().to_string());
+            }
+        }
+
+        Ok(ZipFileSystem {
+            archive: Mutex::new(archive),
+            files: files,
+        })
+    }
+
+    /// Gets the paths of all files, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+}
+
+impl FileSystem for ZipFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let name = try!(self.files.get(path)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", path))));
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = try!(archive.by_name(name));
+
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+        Ok(data)
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        if !self.is_dir(dir) {
+            return Err(Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", dir)));
+        }
+
+        let prefix = if dir.len() == 0 { String::new() } else { format!("{}/", dir) };
+        Ok(self.files.keys()
+            .filter(|p| p.starts_with(&prefix) && !p[prefix.len()..].contains('/'))
+            .map(|p| p[prefix.len()..].to_string())
+            .collect())
+    }
+
+    fn write(&self, path: &str, _data: &[u8]) -> Result<(), Error> {
+        Err(Error::new(ErrorKind::PermissionDenied, format!("Can't write \"{}\", archives are read-only", path)))
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn is_dir(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        dir.len() == 0 || self.files.keys().any(|p| p.starts_with(&prefix))
+    }
+}
+
+/// Writes all files in a memory file system to a new zip archive.
+pub fn write_zip<P: AsRef<Path>>(files: &MemoryFileSystem, path: P) -> Result<(), Error> {
+    let mut zip = ZipWriter::new(try!(File::create(path)));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for file_path in files.paths() {
+        try!(zip.start_file(file_path.as_str(), options));
+        try!(zip.write_all(&try!(files.read(&file_path))));
+    }
+
+    try!(zip.finish());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::process;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+    use vfs::{FileSystem, MemoryFileSystem};
+    use super::{ZipFileSystem, write_zip};
+
+    #[test]
+    fn zip_roundtrip() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-data-archive-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("test.zip");
+
+        let files = MemoryFileSystem::new();
+        files.write_text("descriptor.mod", "name = Test", false).unwrap();
+        files.write_text("history/states/1-Foo.txt", "state = { id = 1 }", true).unwrap();
+        write_zip(&files, &zip_path).unwrap();
+
+        let zip = ZipFileSystem::open(&zip_path).unwrap();
+        assert_eq!(zip.list("").unwrap(), vec!["descriptor.mod"]);
+        assert_eq!(zip.list("history/states").unwrap(), vec!["1-Foo.txt"]);
+        assert_eq!(zip.read_text("history/states/1-Foo.txt").unwrap(), "state = { id = 1 }");
+        assert!(zip.write("foo.txt", &[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_backslash_entries() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-data-archive-backslash-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("test.zip");
+
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("history\\states\\1-Foo.txt", FileOptions::default()).unwrap();
+        zip.write_all(b"state = { id = 1 }").unwrap();
+        zip.finish().unwrap();
+
+        let zip = ZipFileSystem::open(&zip_path).unwrap();
+        assert_eq!(zip.list("history/states").unwrap(), vec!["1-Foo.txt"]);
+        assert_eq!(zip.read_text("history/states/1-Foo.txt").unwrap(), "state = { id = 1 }");
+        assert!(zip.read("history/states/2-Bar.txt").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
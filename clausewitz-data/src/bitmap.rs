@@ -0,0 +1,147 @@
+//! Uncompressed Windows bitmaps, the format Paradox games use for their map images like
+//! `provinces.bmp`.
+
+/// An RGB image, stored top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Bitmap {
+            width: width,
+            height: height,
+            pixels: vec![[0, 0, 0]; (width * height) as usize],
+        }
+    }
+
+    /// Decodes a 24 or 32 bits per pixel uncompressed bitmap.
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        if data.len() < 54 || &data[0..2] != b"BM" {
+            return Err("Not a bitmap".into());
+        }
+
+        let offset = read_u32(data, 10) as usize;
+        let width = read_u32(data, 18) as i32;
+        let height = read_u32(data, 22) as i32;
+        let bits = read_u16(data, 28);
+        let compression = read_u32(data, 30);
+
+        if width <= 0 || height == 0 {
+            return Err(format!("Invalid size {}x{}", width, height));
+        }
+        if compression != 0 && compression != 3 {
+            return Err(format!("Unsupported compression {}", compression));
+        }
+        if bits != 24 && bits != 32 {
+            return Err(format!("Unsupported {} bits per pixel, expected 24 or 32", bits));
+        }
+
+        // Rows are padded to 4 bytes, and stored bottom row first unless the height is negative
+        let bytes_per_pixel = (bits / 8) as usize;
+        let row_size = (width as usize * bytes_per_pixel + 3) / 4 * 4;
+        let top_down = height < 0;
+        let height = height.abs() as u32;
+        let width = width as u32;
+
+        if data.len() < offset + row_size * height as usize {
+            return Err("Pixel data is truncated".into());
+        }
+
+        let mut bitmap = Bitmap::new(width, height);
+        for y in 0..height {
+            let row = if top_down { y } else { height - 1 - y };
+            let start = offset + row as usize * row_size;
+            for x in 0..width {
+                let i = start + x as usize * bytes_per_pixel;
+                // Pixels are stored as BGR
+                bitmap.set_pixel(x, y, [data[i + 2], data[i + 1], data[i]]);
+            }
+        }
+
+        Ok(bitmap)
+    }
+
+    /// Encodes the image as a 24 bits per pixel bitmap.
+    pub fn encode(&self) -> Vec<u8> {
+        let row_size = (self.width as usize * 3 + 3) / 4 * 4;
+        let size = 54 + row_size * self.height as usize;
+
+        let mut data = Vec::with_capacity(size);
+        data.extend_from_slice(b"BM");
+        write_u32(&mut data, size as u32);
+        write_u32(&mut data, 0);
+        write_u32(&mut data, 54);
+        write_u32(&mut data, 40);
+        write_u32(&mut data, self.width);
+        write_u32(&mut data, self.height);
+        data.extend_from_slice(&[1, 0, 24, 0]);
+        write_u32(&mut data, 0);
+        write_u32(&mut data, (row_size * self.height as usize) as u32);
+        write_u32(&mut data, 2835);
+        write_u32(&mut data, 2835);
+        write_u32(&mut data, 0);
+        write_u32(&mut data, 0);
+
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let color = self.pixel(x, y);
+                data.extend_from_slice(&[color[2], color[1], color[0]]);
+            }
+            for _ in self.width as usize * 3..row_size {
+                data.push(0);
+            }
+        }
+
+        data
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 3]) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    data[at] as u16 | (data[at + 1] as u16) << 8
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    read_u16(data, at) as u32 | (read_u16(data, at + 2) as u32) << 16
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bitmap;
+
+    #[test]
+    fn bitmap_roundtrip() {
+        let mut bitmap = Bitmap::new(3, 2);
+        bitmap.set_pixel(0, 0, [255, 0, 0]);
+        bitmap.set_pixel(2, 1, [0, 0, 255]);
+
+        let data = bitmap.encode();
+        assert_eq!(data.len(), 54 + 12 * 2);
+        assert_eq!(Bitmap::decode(&data).unwrap(), bitmap);
+        assert!(Bitmap::decode(&data[..60]).is_err());
+        assert!(Bitmap::decode(b"PNG").is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! On-disk cache of parsed files, so unchanged files don't need to be parsed again every run.
+
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ::{CwValue, CwTable, CwKeyValue, CwSource};
+
+// Bump the version whenever the encoding changes, so old cache entries are ignored
+const MAGIC: &'static [u8] = b"CWC\x01";
+
+/// Numbers temporary entry files, so threads writing entries at the same time don't collide.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self, String> {
+        let dir = dir.into();
+        try!(fs::create_dir_all(&dir).map_err(|e| e.to_string()));
+
+        Ok(ParseCache {
+            dir: dir,
+        })
+    }
+
+    /// Gets the cached result for a file without reading it, if it has the same size and
+    /// modification time as when it was cached. Files with an unknown modification time, 0, are
+    /// never taken to be unchanged.
+    pub fn get(&self, name: &str, size: u64, mtime: u64) -> Option<CwTable> {
+        if mtime == 0 {
+            return None;
+        }
+        read_entry(&self.entry_path(name), |k| k.path == name && k.size == size && k.mtime == mtime)
+    }
+
+    /// Parses text that was read from a file, re-using the cached result if the text hasn't
+    /// changed since it was cached. The name is used to identify the entry, the file's size and
+    /// modification time are stored for `get`.
+    pub fn parse(&self, name: &str, text: &str, size: u64, mtime: u64) -> Result<CwTable, String> {
+        let key = CacheKey {
+            path: name.into(),
+            size: size,
+            mtime: mtime,
+            hash: fnv1a(text.as_bytes()),
+        };
+        self.parse_entry(&self.entry_path(name), key, text)
+    }
+
+    fn entry_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.cwc", fnv1a(name.as_bytes())))
+    }
+
+    fn parse_entry(&self, entry_path: &Path, key: CacheKey, text: &str) -> Result<CwTable, String> {
+        // If there's a matching entry, use that
+        if let Some(table) = read_entry(entry_path, |k| k == &key) {
+            return Ok(table);
+        }
+
+        // There isn't, parse the file and store the result for next time
+        debug!("Cache miss for {}", key.path);
+        let table = try!(CwTable::try_parse(text));
+        if let Err(e) = write_entry(entry_path, &key, &table) {
+            warn!("Failed to write cache entry for {}: {}", key.path, e);
+        }
+
+        Ok(table)
+    }
+}
+
+#[derive(PartialEq)]
+struct CacheKey {
+    path: String,
+    size: u64,
+    mtime: u64,
+    hash: u64,
+}
+
+fn read_entry<F: Fn(&CacheKey) -> bool>(entry_path: &Path, matches: F) -> Option<CwTable> {
+    let mut data = Vec::new();
+    if File::open(entry_path).and_then(|mut f| f.read_to_end(&mut data)).is_err() {
+        return None;
+    }
+
+    let mut reader = Reader { data: &data, position: 0 };
+    if reader.bytes(MAGIC.len()) != Some(MAGIC) {
+        return None;
+    }
+
+    // Different files can end up at the same entry, so check the path as well
+    let entry_key = CacheKey {
+        path: try_opt!(reader.string()),
+        size: try_opt!(reader.u64()),
+        mtime: try_opt!(reader.u64()),
+        hash: try_opt!(reader.u64()),
+    };
+    if !matches(&entry_key) {
+        return None;
+    }
+
+    decode_table(&mut reader)
+}
+
+fn write_entry(entry_path: &Path, key: &CacheKey, table: &CwTable) -> Result<(), String> {
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    write_string(&mut data, &key.path);
+    write_u64(&mut data, key.size);
+    write_u64(&mut data, key.mtime);
+    write_u64(&mut data, key.hash);
+    encode_table(&mut data, table);
+
+    // Write to a temporary file first so a crash can't leave a half-written entry behind
+    let temp = NEXT_TEMP.fetch_add(1, Ordering::SeqCst);
+    let temp_path = entry_path.with_extension(format!("{}-{}.tmp", process::id(), temp));
+    try!(File::create(&temp_path).and_then(|mut f| f.write_all(&data)).map_err(|e| e.to_string()));
+    try!(fs::rename(&temp_path, entry_path).map_err(|e| e.to_string()));
+
+    Ok(())
+}
+
+/// FNV-1a, used because it's simple and gives the same result on every run and platform.
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn encode_table(target: &mut Vec<u8>, table: &CwTable) {
+    write_u32(target, table.values.len() as u32);
+    for key_value in &table.values {
+        write_string(target, &key_value.key);
+
+        // File and layer aren't stored, they're set again after loading
+        match key_value.source {
+            Some(ref source) => {
+                target.push(1);
+                write_u32(target, source.line);
+                write_u32(target, source.column);
+            },
+            None => target.push(0),
+        }
+
+        encode_value(target, &key_value.value);
+    }
+}
+
+fn encode_value(target: &mut Vec<u8>, value: &CwValue) {
+    match value {
+        &CwValue::String(ref v) => {
+            target.push(0);
+            write_string(target, v);
+        },
+        &CwValue::Table(ref t) => {
+            target.push(1);
+            encode_table(target, t);
+        },
+        &CwValue::Array(ref a) => {
+            target.push(2);
+            write_u32(target, a.len() as u32);
+            for val in a {
+                encode_value(target, val);
+            }
+        }
+    }
+}
+
+fn decode_table(reader: &mut Reader) -> Option<CwTable> {
+    let count = try_opt!(reader.u32());
+    let mut table = CwTable::new();
+    for _ in 0..count {
+        let key = try_opt!(reader.string());
+        let source = match try_opt!(reader.u8()) {
+            0 => None,
+            _ => Some(CwSource::new(try_opt!(reader.u32()), try_opt!(reader.u32()))),
+        };
+        let value = try_opt!(decode_value(reader));
+
+        table.values.push(CwKeyValue {
+            key: key,
+            value: value,
+            source: source,
+        });
+    }
+
+    Some(table)
+}
+
+fn decode_value(reader: &mut Reader) -> Option<CwValue> {
+    match try_opt!(reader.u8()) {
+        0 => reader.string().map(CwValue::String),
+        1 => decode_table(reader).map(CwValue::Table),
+        2 => {
+            let count = try_opt!(reader.u32());
+            let mut array = Vec::new();
+            for _ in 0..count {
+                array.push(try_opt!(decode_value(reader)));
+            }
+            Some(CwValue::Array(array))
+        },
+        _ => None,
+    }
+}
+
+fn write_u32(target: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        target.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn write_u64(target: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        target.push((value >> (i * 8)) as u8);
+    }
+}
+
+fn write_string(target: &mut Vec<u8>, value: &str) {
+    write_u32(target, value.len() as u32);
+    target.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.position + count > self.data.len() {
+            return None;
+        }
+
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| b.iter().rev().fold(0, |acc, v| (acc << 8) | *v as u32))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|b| b.iter().rev().fold(0, |acc, v| (acc << 8) | *v as u64))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = try_opt!(self.u32()) as usize;
+        self.bytes(length).and_then(|b| String::from_utf8(b.to_vec()).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use ::CwTable;
+    use super::{ParseCache, CacheKey, Reader, encode_table, decode_table, write_entry, fnv1a};
+
+    #[test]
+    fn encode_roundtrip() {
+        let table = CwTable::parse("foo=bar\nbar={chickens=\"frogs and toads\"}\narray={1 2 3}");
+        let mut data = Vec::new();
+        encode_table(&mut data, &table);
+
+        let decoded = decode_table(&mut Reader { data: &data, position: 0 }).unwrap();
+        assert_eq!(decoded.serialize(), table.serialize());
+        assert_eq!(decoded.source_of("bar"), table.source_of("bar"));
+        assert!(decode_table(&mut Reader { data: &data[..data.len() - 1], position: 0 }).is_none());
+    }
+
+    #[test]
+    fn cache_get_unchanged_file() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-data-cache-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ParseCache::new(&dir).unwrap();
+
+        assert!(cache.get("game:foo.txt", 7, 5).is_none());
+        assert_eq!(cache.parse("game:foo.txt", "foo=bar", 7, 5).unwrap().serialize(), "foo = bar\n");
+        assert_eq!(cache.get("game:foo.txt", 7, 5).unwrap().serialize(), "foo = bar\n");
+        assert!(cache.get("game:foo.txt", 8, 5).is_none());
+        assert!(cache.get("game:foo.txt", 7, 6).is_none());
+        assert!(cache.get("mod:foo.txt", 7, 5).is_none());
+
+        // Without a modification time a file might have changed without its size changing
+        cache.parse("game:bar.txt", "bar=foo", 7, 0).unwrap();
+        assert!(cache.get("game:bar.txt", 7, 0).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_hit_for_same_text() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-data-cache-hit-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ParseCache::new(&dir).unwrap();
+
+        assert_eq!(cache.parse("game:foo.txt", "foo=bar", 7, 1).unwrap().serialize(), "foo = bar\n");
+        let key = CacheKey { path: "game:foo.txt".into(), size: 7, mtime: 1, hash: fnv1a(b"foo=bar") };
+        write_entry(&cache.entry_path("game:foo.txt"), &key, &CwTable::parse("foo=cached")).unwrap();
+        assert_eq!(cache.parse("game:foo.txt", "foo=bar", 7, 1).unwrap().serialize(), "foo = cached\n");
+        assert_eq!(cache.parse("game:foo.txt", "foo=baz", 7, 1).unwrap().serialize(), "foo = baz\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use combine::*;
-use ::{CwValue, CwTable, CwKeyValue};
+use combine::primitives::SourcePosition;
+use ::{CwValue, CwTable, CwKeyValue, CwSource};
 
 fn nl_ws<I>(input: I) -> ParseResult<(), I>
     where I: Stream<Item=char>
@@ -10,7 +11,7 @@ fn nl_ws<I>(input: I) -> ParseResult<(), I>
     nl_ws.parse_state(input)
 }
 
-fn word<I>(input: I) -> ParseResult<String, I>
+pub fn word<I>(input: I) -> ParseResult<String, I>
     where I: Stream<Item=char>
 {
     let word_char = satisfy(|c: char|
@@ -49,7 +50,7 @@ fn string_char<I>(input: I) -> ParseResult<char, I>
     }
 }
 
-fn string_literal<I>(input: I) -> ParseResult<String, I>
+pub fn string_literal<I>(input: I) -> ParseResult<String, I>
     where I: Stream<Item=char>
 {
     let literal = between(
@@ -62,7 +63,7 @@ fn string_literal<I>(input: I) -> ParseResult<String, I>
 }
 
 fn eu4value<I>(input: I) -> ParseResult<CwValue, I>
-    where I: Stream<Item=char>
+    where I: Stream<Item=char, Position=SourcePosition>
 {
     let value =
         parser(word)
@@ -86,31 +87,36 @@ fn eu4value<I>(input: I) -> ParseResult<CwValue, I>
 }
 
 fn key_value<I>(input: I) -> ParseResult<CwKeyValue, I>
-    where I: Stream<Item=char>
+    where I: Stream<Item=char, Position=SourcePosition>
 {
+    // Keep track of where the key starts so we can tell where values came from later
+    let position = input.position();
     let key_value = (parser(word), spaces(), token('='), spaces(), parser(eu4value))
-        .map(|v| CwKeyValue {
+        .map(move |v| CwKeyValue {
             key: v.0,
             value: v.4,
+            source: Some(CwSource::new(position.line as u32, position.column as u32)),
         });
 
     key_value.expected("key-value").parse_state(input)
 }
 
 fn keyless_value<I>(input: I) -> ParseResult<CwKeyValue, I>
-    where I: Stream<Item=char>
+    where I: Stream<Item=char, Position=SourcePosition>
 {
+    let position = input.position();
     let key_value = parser(eu4value)
-        .map(|v| CwKeyValue {
+        .map(move |v| CwKeyValue {
             key: "".into(),
             value: v,
+            source: Some(CwSource::new(position.line as u32, position.column as u32)),
         });
 
     key_value.expected("keyless value").parse_state(input)
 }
 
 fn table<I>(input: I) -> ParseResult<CwTable, I>
-    where I: Stream<Item=char>
+    where I: Stream<Item=char, Position=SourcePosition>
 {
     let table = many(try(parser(key_value)).or(parser(keyless_value)).skip(skip_many(parser(nl_ws))))
         .map(|v| {
@@ -123,11 +129,22 @@ fn table<I>(input: I) -> ParseResult<CwTable, I>
 }
 
 fn eu4data<I>(input: I) -> ParseResult<CwTable, I>
-    where I: Stream<Item=char>
+    where I: Stream<Item=char, Position=SourcePosition>
 {
     parser(table).parse_state(input)
 }
 
-pub fn parse(text: &str) -> CwTable {
-    parser(eu4data).parse(text).unwrap().0
+/// Parses as much of the text as possible, anything after the first entry that can't be parsed is
+/// ignored.
+pub fn parse(text: &str) -> Result<CwTable, String> {
+    parser(eu4data).parse(State::new(text))
+        .map(|v| v.0)
+        .map_err(|e| format!("{}", e))
+}
+
+/// Parses the whole text, failing if any of it can't be parsed.
+pub fn parse_all(text: &str) -> Result<CwTable, String> {
+    (parser(eu4data), eof()).map(|v| v.0).parse(State::new(text))
+        .map(|v| v.0)
+        .map_err(|e| format!("{}", e))
 }
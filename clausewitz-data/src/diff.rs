@@ -0,0 +1,136 @@
+//! Structural differences between two tables, for showing what changed between two versions of
+//! a file regardless of formatting.
+
+use std::fmt;
+use std::collections::HashMap;
+use {CwTable, CwValue};
+
+/// A single entry that's different, identified by the keys leading to it like "state/history/owner".
+/// Keys that appear more than once get the number of the occurrence, like "add_core_of[2]".
+#[derive(Debug, Clone, PartialEq)]
+pub struct CwChange {
+    pub path: String,
+    /// The old value, None if the entry was added.
+    pub old: Option<String>,
+    /// The new value, None if the entry was removed.
+    pub new: Option<String>,
+}
+
+impl fmt::Display for CwChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (&None, &Some(ref new)) => write!(f, "+ {} = {}", self.path, new),
+            (&Some(ref old), &None) => write!(f, "- {} = {}", self.path, old),
+            (&Some(ref old), &Some(ref new)) => write!(f, "~ {}: {} -> {}", self.path, old, new),
+            (&None, &None) => write!(f, "  {}", self.path),
+        }
+    }
+}
+
+/// Gets what changed from one table to another. Entries are matched by key, in order of
+/// occurrence, and tables present in both are compared entry by entry.
+pub fn diff_tables(old: &CwTable, new: &CwTable) -> Vec<CwChange> {
+    let mut changes = Vec::new();
+    diff_into(old, new, "", &mut changes);
+    changes
+}
+
+fn diff_into(old: &CwTable, new: &CwTable, prefix: &str, changes: &mut Vec<CwChange>) {
+    let old_keys = KeyIndex::new(old);
+    let new_keys = KeyIndex::new(new);
+    let path_of = |key: &str, lower: &str, occurrence: usize| {
+        let total = old_keys.count(lower).max(new_keys.count(lower));
+        let key = if total > 1 { format!("{}[{}]", key, occurrence + 1) } else { key.to_string() };
+        if prefix.len() == 0 { key } else { format!("{}/{}", prefix, key) }
+    };
+
+    for (i, entry) in old.values.iter().enumerate() {
+        let (ref lower, occurrence) = old_keys.keys[i];
+        let path = path_of(&entry.key, lower, occurrence);
+        match new_keys.find(lower, occurrence).map(|j| &new.values[j].value) {
+            Some(new_value) => match (&entry.value, new_value) {
+                (&CwValue::Table(ref old_table), &CwValue::Table(ref new_table)) =>
+                    diff_into(old_table, new_table, &path, changes),
+                (old_value, new_value) => {
+                    let (old_text, new_text) = (compact(old_value), compact(new_value));
+                    if old_text != new_text {
+                        changes.push(CwChange { path: path, old: Some(old_text), new: Some(new_text) });
+                    }
+                },
+            },
+            None => changes.push(CwChange { path: path, old: Some(compact(&entry.value)), new: None }),
+        }
+    }
+
+    for (i, entry) in new.values.iter().enumerate() {
+        let (ref lower, occurrence) = new_keys.keys[i];
+        if old_keys.find(lower, occurrence).is_none() {
+            changes.push(CwChange { path: path_of(&entry.key, lower, occurrence), old: None, new: Some(compact(&entry.value)) });
+        }
+    }
+}
+
+/// The entries of a table by lowercase key and occurrence, built once so matching entries doesn't
+/// rescan the table.
+struct KeyIndex {
+    /// For every entry its lowercase key and how many entries with the same key come before it.
+    keys: Vec<(String, usize)>,
+    /// The positions of the entries with each lowercase key, in order.
+    positions: HashMap<String, Vec<usize>>,
+}
+
+impl KeyIndex {
+    fn new(table: &CwTable) -> Self {
+        let mut keys = Vec::with_capacity(table.values.len());
+        let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entry) in table.values.iter().enumerate() {
+            let key = entry.key.to_lowercase();
+            let same = positions.entry(key.clone()).or_insert_with(Vec::new);
+            keys.push((key, same.len()));
+            same.push(i);
+        }
+
+        KeyIndex {
+            keys: keys,
+            positions: positions,
+        }
+    }
+
+    fn count(&self, key: &str) -> usize {
+        self.positions.get(key).map(|p| p.len()).unwrap_or(0)
+    }
+
+    fn find(&self, key: &str, occurrence: usize) -> Option<usize> {
+        self.positions.get(key).and_then(|p| p.get(occurrence)).cloned()
+    }
+}
+
+/// Writes a value on a single line.
+fn compact(value: &CwValue) -> String {
+    let mut text = String::new();
+    value.serialize_to(&mut text);
+    text.lines().map(|l| l.trim()).filter(|l| l.len() != 0).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use CwTable;
+    use super::{CwChange, diff_tables};
+
+    #[test]
+    fn diff_changes() {
+        let old = CwTable::parse("state = { id = 1 manpower = 100 provinces = { 1 2 } \
+            history = { owner = FRA add_core_of = FRA add_core_of = GER } }");
+        let new = CwTable::parse("state = { id = 1 manpower = 200 provinces = { 1 2 } \
+            history = { owner = FRA add_core_of = FRA buildings = { infrastructure = 3 } } }");
+
+        let changes = diff_tables(&old, &new);
+        assert_eq!(changes, vec![
+            CwChange { path: "state/manpower".into(), old: Some("100".into()), new: Some("200".into()) },
+            CwChange { path: "state/history/add_core_of[2]".into(), old: Some("GER".into()), new: None },
+            CwChange { path: "state/history/buildings".into(), old: None, new: Some("{ infrastructure = 3 }".into()) },
+        ]);
+        assert_eq!(changes[0].to_string(), "~ state/manpower: 100 -> 200");
+        assert!(diff_tables(&old, &old).is_empty());
+    }
+}
@@ -1,7 +1,23 @@
+#[macro_use] extern crate log;
 extern crate combine;
+extern crate zip;
+
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(v) => v, None => return None })
+}
 
 mod combinators;
+pub mod archive;
+pub mod bitmap;
+pub mod cache;
+pub mod diff;
 pub mod file;
+pub mod localisation;
+pub mod vdf;
+pub mod vfs;
+
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum CwValue {
@@ -47,6 +63,14 @@ impl CwValue {
         }
     }
 
+    pub fn as_array(&self) -> Option<&Vec<CwValue>> {
+        if let &CwValue::Array(ref val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
     pub fn as_array_mut(&mut self) -> Option<&mut Vec<CwValue>> {
         if let &mut CwValue::Array(ref mut val) = self {
             Some(val)
@@ -90,6 +114,52 @@ impl From<CwTable> for CwValue {
 pub struct CwKeyValue {
     pub key: String,
     pub value: CwValue,
+    pub source: Option<CwSource>,
+}
+
+impl CwKeyValue {
+    pub fn new<S: Into<String>>(key: S, value: CwValue) -> Self {
+        CwKeyValue {
+            key: key.into(),
+            value: value,
+            source: None,
+        }
+    }
+}
+
+/// Where a value was defined, so it can be traced back when several files define the same thing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CwSource {
+    pub file: Option<Arc<String>>,
+    pub line: u32,
+    pub column: u32,
+    pub layer: Option<Arc<String>>,
+}
+
+impl CwSource {
+    pub fn new(line: u32, column: u32) -> Self {
+        CwSource {
+            file: None,
+            line: line,
+            column: column,
+            layer: None,
+        }
+    }
+}
+
+impl fmt::Display for CwSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => try!(write!(f, "{}:{}:{}", file, self.line, self.column)),
+            None => try!(write!(f, "<unknown>:{}:{}", self.line, self.column)),
+        }
+
+        if let Some(ref layer) = self.layer {
+            try!(write!(f, " ({})", layer));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -105,7 +175,13 @@ impl CwTable {
     }
 
     pub fn parse(text: &str) -> CwTable {
-        combinators::parse(text)
+        combinators::parse(text).unwrap()
+    }
+
+    /// Parses the text, returning a description of the problem if it isn't valid. Unlike
+    /// `parse`, this fails if not all of the text could be parsed.
+    pub fn try_parse(text: &str) -> Result<CwTable, String> {
+        combinators::parse_all(text)
     }
 
     pub fn serialize(&self) -> String {
@@ -129,32 +205,100 @@ impl CwTable {
         self.values.iter().find(|v| v.key.to_lowercase() == key.to_lowercase()).map(|v| &v.value)
     }
 
+    pub fn get_key_value(&self, key: &str) -> Option<&CwKeyValue> {
+        self.values.iter().find(|v| v.key.to_lowercase() == key.to_lowercase())
+    }
+
+    /// Gets all values with a key, for keys that can be given more than once.
+    pub fn get_all(&self, key: &str) -> Vec<&CwValue> {
+        self.values.iter().filter(|v| v.key.to_lowercase() == key.to_lowercase()).map(|v| &v.value).collect()
+    }
+
+    /// Gets the table with a key, adding an empty one if there isn't any yet. Returns None if the
+    /// key has a value that isn't a table.
+    pub fn get_table_or_insert(&mut self, key: &str) -> Option<&mut CwTable> {
+        if !self.has_key(key) {
+            self.add(key, CwTable::new().into());
+        }
+
+        let value = self.get_mut(key).unwrap();
+
+        // An empty table is parsed as an empty array
+        if value.as_array().map(|a| a.len() == 0).unwrap_or(false) {
+            *value = CwTable::new().into();
+        }
+
+        value.as_table_mut()
+    }
+
+    /// Removes all values with a key.
+    pub fn remove_all(&mut self, key: &str) {
+        self.values.retain(|v| v.key.to_lowercase() != key.to_lowercase());
+    }
+
+    /// Gets where the value for a key was defined, if known.
+    pub fn source_of(&self, key: &str) -> Option<&CwSource> {
+        self.get_key_value(key).and_then(|v| v.source.as_ref())
+    }
+
     pub fn get_mut(&mut self, key: &str) -> Option<&mut CwValue> {
         self.values.iter_mut().find(|v| v.key.to_lowercase() == key.to_lowercase()).map(|v| &mut v.value)
     }
 
     pub fn set(&mut self, key: &str, value: CwValue) {
         // Check if a value already exists with this key
-        if let Some(entry) = self.get_mut(key) {
-            // It does, overwrite it
-            *entry = value;
+        if let Some(entry) = self.values.iter_mut().find(|v| v.key.to_lowercase() == key.to_lowercase()) {
+            // It does, overwrite it, it no longer comes from where the old value was defined
+            entry.value = value;
+            entry.source = None;
             return; // < Can't use else, borrow checking complains
         }
 
         // It doesn't, add it
-        self.values.push(CwKeyValue { key: key.into(), value: value });
+        self.values.push(CwKeyValue::new(key, value));
     }
 
     pub fn add(&mut self, key: &str, value: CwValue) {
-        self.values.push(CwKeyValue { key: key.into(), value: value });
+        self.values.push(CwKeyValue::new(key, value));
     }
 
     pub fn has_key(&self, key: &str) -> bool {
         self.values.iter().any(|v| v.key.to_lowercase() == key.to_lowercase())
     }
+
+    /// Marks all values in this table and its children as coming from a file and layer, for
+    /// example "history/states/1-France.txt" in the base game.
+    pub fn set_source(&mut self, file: &str, layer: Option<&str>) {
+        let file = Arc::new(String::from(file));
+        let layer = layer.map(|l| Arc::new(String::from(l)));
+        self.set_source_shared(&file, &layer);
+    }
+
+    fn set_source_shared(&mut self, file: &Arc<String>, layer: &Option<Arc<String>>) {
+        for key_value in &mut self.values {
+            if let Some(ref mut source) = key_value.source {
+                source.file = Some(file.clone());
+                source.layer = layer.clone();
+            }
+
+            key_value.value.set_source_shared(file, layer);
+        }
+    }
 }
 
 impl CwValue {
+    fn set_source_shared(&mut self, file: &Arc<String>, layer: &Option<Arc<String>>) {
+        match self {
+            &mut CwValue::String(_) => {},
+            &mut CwValue::Table(ref mut t) => t.set_source_shared(file, layer),
+            &mut CwValue::Array(ref mut a) => {
+                for val in a {
+                    val.set_source_shared(file, layer);
+                }
+            }
+        }
+    }
+
     fn serialize_to(&self, target: &mut String) {
         match self {
             &CwValue::String(ref v) => {
@@ -297,6 +441,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn try_parse_incomplete() {
+        assert!(CwTable::try_parse("foo={bar=chickens").is_err());
+        assert!(CwTable::try_parse("foo={bar=chickens} # done\n").is_ok());
+        assert_keystr(&CwTable::parse("foo=bar\nbar={").values[0], "foo", "bar");
+    }
+
+    #[test]
+    fn parse_source() {
+        let mut data = CwTable::parse("foo=bar\n\nbar={\n  foobar=frogs\n}");
+        data.set_source("common/foo.txt", Some("game"));
+
+        let source = data.source_of("bar").unwrap();
+        assert_eq!((source.line, source.column), (3, 1));
+        let nested = data.get("bar").unwrap().as_table().unwrap().source_of("foobar").unwrap();
+        assert_eq!((nested.line, nested.column), (4, 3));
+        assert_eq!(nested.to_string(), "common/foo.txt:4:3 (game)");
+
+        data.set("bar", "cheeze".into());
+        assert!(data.source_of("bar").is_none());
+    }
+
     fn assert_keystr(cw_value: &CwKeyValue, key: &str, value: &str) {
         assert_eq!(cw_value.key, key);
         assert_valuestr(&cw_value.value, value);
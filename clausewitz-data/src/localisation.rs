@@ -0,0 +1,176 @@
+//! Localisation files, the YAML-like `*_l_<language>.yml` files that map keys to the text shown
+//! in the game.
+
+use std::collections::BTreeMap;
+
+/// Localised text for all loaded languages.
+#[derive(Debug, Clone, Default)]
+pub struct CwLocalisation {
+    languages: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl CwLocalisation {
+    pub fn new() -> Self {
+        CwLocalisation {
+            languages: BTreeMap::new(),
+        }
+    }
+
+    /// Adds all entries in a localisation file, replacing existing entries with the same key.
+    pub fn add_file(&mut self, text: &str) -> Result<(), String> {
+        let (language, entries) = try!(parse(text));
+        let language = self.languages.entry(language).or_insert_with(BTreeMap::new);
+        for (key, value) in entries {
+            language.insert(key, value);
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, language: &str, key: &str, value: &str) {
+        self.languages.entry(language.into()).or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, language: &str, key: &str) -> Option<&String> {
+        self.languages.get(language).and_then(|l| l.get(key))
+    }
+
+    pub fn languages(&self) -> Vec<&String> {
+        self.languages.keys().collect()
+    }
+
+    pub fn entries(&self, language: &str) -> Vec<(&String, &String)> {
+        self.languages.get(language).map(|l| l.iter().collect()).unwrap_or_default()
+    }
+}
+
+/// Parses a localisation file into its language, for example "english", and its entries.
+pub fn parse(text: &str) -> Result<(String, Vec<(String, String)>), String> {
+    let mut language = None;
+    let mut entries = Vec::new();
+
+    for (i, line) in text.trim_left_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with('#') {
+            continue;
+        }
+
+        // The first line is the language header, like "l_english:", which can have a version
+        // number and a comment after it like entries can
+        if language.is_none() {
+            let header = line.split('#').next().unwrap_or("").trim();
+            match header.find(':') {
+                Some(colon) if header.starts_with("l_") && header[colon+1..].chars().all(|c| c.is_digit(10)) => {
+                    language = Some(header[2..colon].to_string());
+                    continue;
+                },
+                _ => return Err(format!("Line {}: expected a language header like \"l_english:\"", i + 1)),
+            }
+        }
+
+        // Entries look like KEY:0 "Value", the version number is optional
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => return Err(format!("Line {}: expected \"key: value\"", i + 1)),
+        };
+        let key = line[..colon].trim();
+        let value = match quoted_value(&line[colon+1..]) {
+            Some(value) => value,
+            None => return Err(format!("Line {}: expected a quoted value", i + 1)),
+        };
+
+        entries.push((key.to_string(), value));
+    }
+
+    match language {
+        Some(language) => Ok((language, entries)),
+        None => Err("No language header found".into()),
+    }
+}
+
+/// Gets the text between the quotes in the rest of an entry's line. The game allows quotes inside
+/// the text without escaping them, so the closing quote is the one followed by nothing but an
+/// optional comment.
+fn quoted_value(rest: &str) -> Option<String> {
+    let start = match rest.find('"') {
+        Some(start) => start + 1,
+        None => return None,
+    };
+
+    let mut value = String::new();
+    let mut chars = rest[start..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if rest[start+i+1..].starts_with('"') => {
+                chars.next();
+                value.push('"');
+            },
+            '"' => {
+                let after = rest[start+i+1..].trim();
+                if after.len() == 0 || after.starts_with('#') {
+                    return Some(value);
+                }
+                value.push('"');
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Writes entries as a localisation file. The game needs these files to start with a BOM, which
+/// is left to whoever writes the file.
+pub fn serialize(language: &str, entries: &[(String, String)]) -> String {
+    let mut text = format!("l_{}:\n", language);
+    for &(ref key, ref value) in entries {
+        text.push_str(&format!(" {}:0 \"{}\"\n", key, value.replace('"', "\\\"")));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CwLocalisation, parse, serialize};
+
+    #[test]
+    fn parse_localisation() {
+        let text = "\u{feff}l_english:\n # Comment\n STATE_16:0 \"Île-de-France\"\n FRA_ADJ: \"French\" # Trailing\n";
+        let (language, entries) = parse(text).unwrap();
+        assert_eq!(language, "english");
+        assert_eq!(entries, vec![
+            ("STATE_16".to_string(), "Île-de-France".to_string()),
+            ("FRA_ADJ".to_string(), "French".to_string()),
+        ]);
+
+        // Quotes in comments and escaped or bare quotes in the text
+        let text = "l_english:\n A: \"Free \\\"France\\\"\" # The \"free\" one\n B: \"Say \"hi\"\"\n";
+        let (_, entries) = parse(text).unwrap();
+        assert_eq!(entries, vec![
+            ("A".to_string(), "Free \"France\"".to_string()),
+            ("B".to_string(), "Say \"hi\"".to_string()),
+        ]);
+        assert!(parse("l_english:\n A: \"Foo # Bar").is_err());
+
+        // Headers with a version number or a comment
+        assert_eq!(parse("l_french:0\n FRA:0 \"France\"").unwrap().0, "french");
+        assert_eq!(parse("l_german: # Deutsch\n FRA:0 \"Frankreich\"").unwrap().0, "german");
+        assert!(parse("l_english: foo\n FRA:0 \"France\"").is_err());
+
+        assert!(parse("STATE_16:0 \"Foo\"").is_err());
+        assert!(parse("l_english:\n STATE_16:0 Foo").is_err());
+    }
+
+    #[test]
+    fn localisation_roundtrip() {
+        let mut localisation = CwLocalisation::new();
+        localisation.add_file(&serialize("french", &[("FRA".into(), "France".into())])).unwrap();
+        localisation.set("french", "FRA_ADJ", "français");
+        assert_eq!(localisation.get("french", "FRA"), Some(&"France".into()));
+        assert_eq!(localisation.entries("french").len(), 2);
+        assert_eq!(localisation.get("english", "FRA"), None);
+
+        let text = serialize("english", &[("A".into(), "Free \"France\"".into())]);
+        assert_eq!(text, "l_english:\n A:0 \"Free \\\"France\\\"\"\n");
+        assert_eq!(parse(&text).unwrap().1[0].1, "Free \"France\"");
+    }
+}
@@ -0,0 +1,184 @@
+//! Valve's KeyValues format, as used by Steam's `libraryfolders.vdf` and `appmanifest_*.acf`.
+
+use std::path::{Path, PathBuf};
+use combine::*;
+use combinators::{word, string_literal};
+use ::{file, CwValue, CwTable, CwKeyValue};
+
+fn vdf_ws<I>(input: I) -> ParseResult<(), I>
+    where I: Stream<Item=char>
+{
+    let comment = (token('/'), token('/'), skip_many(satisfy(|c| c != '\n'))).map(|_| ());
+    let mut vdf_ws = space().or(newline()).map(|_| ()).or(comment);
+
+    vdf_ws.parse_state(input)
+}
+
+fn vdf_token<I>(input: I) -> ParseResult<String, I>
+    where I: Stream<Item=char>
+{
+    let token = parser(string_literal).or(parser(word));
+
+    token.expected("token").parse_state(input)
+}
+
+fn vdf_value<I>(input: I) -> ParseResult<CwValue, I>
+    where I: Stream<Item=char>
+{
+    let value =
+        parser(vdf_token)
+            .map(|v| CwValue::String(v))
+        .or((token('{'), parser(vdf_table), token('}'))
+            .map(|v| CwValue::Table(v.1)));
+
+    value.expected("value").parse_state(input)
+}
+
+fn vdf_key_value<I>(input: I) -> ParseResult<CwKeyValue, I>
+    where I: Stream<Item=char>
+{
+    // Unlike Clausewitz script, there's no = between the key and the value
+    let key_value = (parser(vdf_token), skip_many(parser(vdf_ws)), parser(vdf_value))
+        .map(|v| CwKeyValue::new(v.0, v.2));
+
+    key_value.expected("key-value").parse_state(input)
+}
+
+fn vdf_table<I>(input: I) -> ParseResult<CwTable, I>
+    where I: Stream<Item=char>
+{
+    let table = many(parser(vdf_key_value).skip(skip_many(parser(vdf_ws))))
+        .map(|v| {
+            CwTable {
+                values: v
+            }
+        });
+
+    (skip_many(parser(vdf_ws)), table).map(|v| v.1).parse_state(input)
+}
+
+pub fn parse(text: &str) -> Result<CwTable, String> {
+    (parser(vdf_table), eof()).map(|v| v.0).parse(text)
+        .map(|v| v.0)
+        .map_err(|e| format!("{}", e))
+}
+
+/// Finds the install directory of a Steam app, searching all library folders known to the Steam
+/// installation at `steam_root`.
+pub fn find_app_install<P: AsRef<Path>>(steam_root: P, app_id: u32) -> Option<PathBuf> {
+    let steam_root = steam_root.as_ref();
+
+    // Load in the additional library folders, if there are any
+    let mut library_folders_file = steam_root.to_path_buf();
+    library_folders_file.push("steamapps/libraryfolders.vdf");
+    let library_folders = match file::read_all_text(&library_folders_file) {
+        Ok(text) => parse(&text).unwrap_or_else(|e| {
+            warn!("Could not parse \"{}\": {}", library_folders_file.display(), e);
+            CwTable::new()
+        }),
+        Err(_) => CwTable::new(),
+    };
+
+    for library in library_paths(steam_root, &library_folders) {
+        // Check if this library has a manifest for the app
+        let mut manifest_file = library.clone();
+        manifest_file.push(format!("steamapps/appmanifest_{}.acf", app_id));
+        let text = match file::read_all_text(&manifest_file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let manifest = match parse(&text) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Could not parse \"{}\": {}", manifest_file.display(), e);
+                continue;
+            }
+        };
+
+        // Find the directory it's installed to
+        let install_dir = manifest.get("AppState")
+            .and_then(|v| v.as_table())
+            .and_then(|t| t.get("installdir"))
+            .and_then(|v| v.as_string());
+        if let Some(install_dir) = install_dir {
+            let mut path = library;
+            path.push("steamapps/common");
+            path.push(install_dir);
+
+            if path.is_dir() {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+fn library_paths(steam_root: &Path, library_folders: &CwTable) -> Vec<PathBuf> {
+    // The Steam root itself is always the first library
+    let mut paths = vec![steam_root.to_path_buf()];
+
+    let folders = match library_folders.get("LibraryFolders").and_then(|v| v.as_table()) {
+        Some(folders) => folders,
+        None => return paths,
+    };
+
+    // Only the numbered keys are libraries, older Steam versions store the path directly while
+    // newer versions store a table with the path in it
+    for entry in folders.values.iter().filter(|v| v.key.parse::<u32>().is_ok()) {
+        let path = match &entry.value {
+            &CwValue::String(ref path) => Some(path),
+            &CwValue::Table(ref table) => table.get("path").and_then(|v| v.as_string()),
+            _ => None,
+        };
+
+        if let Some(path) = path {
+            let path = PathBuf::from(path);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::{parse, library_paths};
+
+    #[test]
+    fn parse_manifest() {
+        let data = parse("\"AppState\"\n{\n\t\"appid\"\t\t\"394360\"\n\t\"installdir\"\t\t\"Hearts of Iron IV\"\n}\n").unwrap();
+        assert_eq!(data.values.len(), 1);
+
+        let app_state = data.get("appstate").unwrap().as_table().unwrap();
+        assert_eq!(app_state.get("appid").unwrap().as_string(), Some(&String::from("394360")));
+        assert_eq!(app_state.get("installdir").unwrap().as_string(), Some(&String::from("Hearts of Iron IV")));
+    }
+
+    #[test]
+    fn parse_comments_and_escapes() {
+        let data = parse("// Written by Steam\n\"path\" \"D:\\\\Games\\\\Steam\" // library\n\"empty\" {}").unwrap();
+        assert_eq!(data.values.len(), 2);
+        assert_eq!(data.get("path").unwrap().as_string(), Some(&String::from("D:\\Games\\Steam")));
+        assert_eq!(data.get("empty").unwrap().as_table().unwrap().values.len(), 0);
+
+        assert!(parse("\"AppState\" { \"appid\" \"394360\"").is_err());
+    }
+
+    #[test]
+    fn library_paths_old_format() {
+        let data = parse("\"LibraryFolders\" { \"TimeNextStatsReport\" \"1\" \"1\" \"/mnt/games/steam\" }").unwrap();
+        let paths = library_paths(Path::new("/home/layl/.steam"), &data);
+        assert_eq!(paths, vec![PathBuf::from("/home/layl/.steam"), PathBuf::from("/mnt/games/steam")]);
+    }
+
+    #[test]
+    fn library_paths_new_format() {
+        let data = parse("\"libraryfolders\" { \"0\" { \"path\" \"/home/layl/.steam\" } \"1\" { \"path\" \"/mnt/games/steam\" \"apps\" { \"394360\" \"1234\" } } }").unwrap();
+        let paths = library_paths(Path::new("/home/layl/.steam"), &data);
+        assert_eq!(paths, vec![PathBuf::from("/home/layl/.steam"), PathBuf::from("/mnt/games/steam")]);
+    }
+}
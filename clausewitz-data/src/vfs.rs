@@ -0,0 +1,397 @@
+//! File system abstraction so game data can come from disk, memory or several sources layered on
+//! top of each other. All paths are relative and use forward slashes, like the game itself does.
+
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::time::UNIX_EPOCH;
+
+pub trait FileSystem: Send + Sync {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error>;
+
+    /// Lists the names of the files directly inside a directory, sorted by name.
+    fn list(&self, dir: &str) -> Result<Vec<String>, Error>;
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), Error>;
+
+    fn is_file(&self, path: &str) -> bool;
+
+    fn is_dir(&self, dir: &str) -> bool;
+
+    /// Gets when a file was last modified in nanoseconds since the unix epoch, if known.
+    fn modified(&self, _path: &str) -> Option<u64> {
+        None
+    }
+
+    /// Gets the size of a file in bytes without reading it, if known.
+    fn size(&self, _path: &str) -> Option<u64> {
+        None
+    }
+
+    /// Gets the name of the source a file comes from, for file systems combining several.
+    fn origin(&self, _path: &str) -> Option<String> {
+        None
+    }
+
+    fn read_text(&self, path: &str) -> Result<String, Error> {
+        let data = try!(self.read(path));
+        let text = try!(String::from_utf8(data).map_err(|e| Error::new(ErrorKind::InvalidData, e)));
+
+        // Trim BOM if needed
+        Ok(text.trim_left_matches('\u{feff}').into())
+    }
+
+    fn write_text(&self, path: &str, text: &str, add_bom: bool) -> Result<(), Error> {
+        let mut data = Vec::new();
+
+        // Add BOM if needed
+        if add_bom {
+            data.extend_from_slice("\u{feff}".as_bytes());
+        }
+
+        data.extend_from_slice(text.as_bytes());
+        self.write(path, &data)
+    }
+}
+
+/// Joins a relative path onto a directory, either of which may be empty.
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.len() == 0 {
+        name.into()
+    } else if name.len() == 0 {
+        dir.into()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Files in a directory on disk.
+pub struct DiskFileSystem {
+    root: PathBuf,
+}
+
+impl DiskFileSystem {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DiskFileSystem {
+            root: root.into(),
+        }
+    }
+
+    pub fn root(&self) -> &PathBuf {
+        &self.root
+    }
+
+    pub fn full_path(&self, path: &str) -> PathBuf {
+        let mut full_path = self.root.clone();
+        full_path.push(path);
+        full_path
+    }
+}
+
+impl FileSystem for DiskFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        try!(try!(fs::File::open(self.full_path(path))).read_to_end(&mut data));
+        Ok(data)
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in try!(fs::read_dir(self.full_path(dir))) {
+            let entry = try!(entry);
+            if try!(entry.file_type()).is_file() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), Error> {
+        use std::io::Write;
+
+        let full_path = self.full_path(path);
+        if let Some(parent) = full_path.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+
+        try!(fs::File::create(full_path)).write_all(data)
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        self.full_path(path).is_file()
+    }
+
+    fn is_dir(&self, dir: &str) -> bool {
+        self.full_path(dir).is_dir()
+    }
+
+    fn modified(&self, path: &str) -> Option<u64> {
+        fs::metadata(self.full_path(path)).ok()
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+    }
+
+    fn size(&self, path: &str) -> Option<u64> {
+        fs::metadata(self.full_path(path)).ok().map(|m| m.len())
+    }
+}
+
+/// Files kept in memory, directories exist implicitly when there are files in them.
+pub struct MemoryFileSystem {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        MemoryFileSystem {
+            files: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Gets the paths of all files, sorted.
+    pub fn paths(&self) -> Vec<String> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        self.files.lock().unwrap().get(path).cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", path)))
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        let prefix = if dir.len() == 0 { String::new() } else { format!("{}/", dir) };
+
+        // The map is sorted, so the names will be as well
+        let names: Vec<_> = self.files.lock().unwrap().keys()
+            .filter(|p| p.starts_with(&prefix) && !p[prefix.len()..].contains('/'))
+            .map(|p| p[prefix.len()..].to_string())
+            .collect();
+
+        if names.len() == 0 && !self.is_dir(dir) {
+            return Err(Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", dir)));
+        }
+
+        Ok(names)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), Error> {
+        self.files.lock().unwrap().insert(path.into(), data.to_vec());
+        Ok(())
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn is_dir(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        dir.len() == 0 || self.files.lock().unwrap().keys().any(|p| p.starts_with(&prefix))
+    }
+}
+
+/// Several file systems layered on top of each other, files in later layers take priority over
+/// files with the same path in earlier layers. Writes go to the top layer.
+pub struct OverlayFileSystem {
+    layers: Vec<OverlayLayer>,
+}
+
+struct OverlayLayer {
+    name: String,
+    files: Box<dyn FileSystem>,
+    replaced_dirs: Vec<String>,
+}
+
+impl OverlayLayer {
+    fn replaces(&self, dir: &str) -> bool {
+        self.replaced_dirs.iter().any(|d| d == dir)
+    }
+}
+
+impl OverlayFileSystem {
+    pub fn new() -> Self {
+        OverlayFileSystem {
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn push_layer(&mut self, name: &str, layer: Box<dyn FileSystem>) {
+        self.layers.push(OverlayLayer {
+            name: name.into(),
+            files: layer,
+            replaced_dirs: Vec::new(),
+        });
+    }
+
+    /// Hides the files directly inside a directory in all layers below the top one, the same way
+    /// `replace_path` in a mod's descriptor does.
+    pub fn replace_dir(&mut self, dir: &str) {
+        if let Some(layer) = self.layers.last_mut() {
+            layer.replaced_dirs.push(dir.into());
+        }
+    }
+
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.layers.iter().map(|l| l.name.as_str()).collect()
+    }
+
+    fn layer_for(&self, path: &str) -> Option<&OverlayLayer> {
+        let dir = match path.rfind('/') {
+            Some(i) => &path[..i],
+            None => "",
+        };
+
+        for layer in self.layers.iter().rev() {
+            if layer.files.is_file(path) {
+                return Some(layer);
+            }
+
+            // Nothing below this layer can be seen in this directory
+            if layer.replaces(dir) {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        match self.layer_for(path) {
+            Some(layer) => layer.files.read(path),
+            None => Err(Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", path))),
+        }
+    }
+
+    fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        if !self.is_dir(dir) {
+            return Err(Error::new(ErrorKind::NotFound, format!("\"{}\" does not exist", dir)));
+        }
+
+        let mut names = Vec::new();
+        for layer in self.layers.iter().rev() {
+            if layer.files.is_dir(dir) {
+                names.extend(try!(layer.files.list(dir)));
+            }
+
+            if layer.replaces(dir) {
+                break;
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), Error> {
+        match self.layers.last() {
+            Some(layer) => layer.files.write(path, data),
+            None => Err(Error::new(ErrorKind::NotFound, "There are no layers to write to")),
+        }
+    }
+
+    fn is_file(&self, path: &str) -> bool {
+        self.layer_for(path).is_some()
+    }
+
+    fn is_dir(&self, dir: &str) -> bool {
+        for layer in self.layers.iter().rev() {
+            if layer.files.is_dir(dir) {
+                return true;
+            }
+
+            if layer.replaces(dir) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    fn modified(&self, path: &str) -> Option<u64> {
+        self.layer_for(path).and_then(|l| l.files.modified(path))
+    }
+
+    fn size(&self, path: &str) -> Option<u64> {
+        self.layer_for(path).and_then(|l| l.files.size(path))
+    }
+
+    fn origin(&self, path: &str) -> Option<String> {
+        self.layer_for(path).map(|l| l.name.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSystem, MemoryFileSystem, OverlayFileSystem};
+
+    #[test]
+    fn memory_list() {
+        let fs = MemoryFileSystem::new();
+        fs.write_text("history/states/2-Bar.txt", "", false).unwrap();
+        fs.write_text("history/states/1-Foo.txt", "", false).unwrap();
+        fs.write_text("history/states/sub/3-Cheeze.txt", "", false).unwrap();
+        fs.write_text("history/statesfoo.txt", "", false).unwrap();
+
+        assert_eq!(fs.list("history/states").unwrap(), vec!["1-Foo.txt", "2-Bar.txt"]);
+        assert!(fs.is_dir("history/states/sub"));
+        assert!(!fs.is_dir("history/state"));
+        assert!(fs.list("common").is_err());
+    }
+
+    #[test]
+    fn memory_text_bom() {
+        let fs = MemoryFileSystem::new();
+        fs.write_text("foo.txt", "foo=bar", true).unwrap();
+        assert_eq!(fs.read("foo.txt").unwrap().len(), 10);
+        assert_eq!(fs.read_text("foo.txt").unwrap(), "foo=bar");
+    }
+
+    #[test]
+    fn overlay_priority() {
+        let base = MemoryFileSystem::new();
+        base.write_text("common/a.txt", "base", false).unwrap();
+        base.write_text("common/b.txt", "base", false).unwrap();
+        let top = MemoryFileSystem::new();
+        top.write_text("common/b.txt", "top", false).unwrap();
+        top.write_text("common/c.txt", "top", false).unwrap();
+
+        let mut fs = OverlayFileSystem::new();
+        fs.push_layer("base", Box::new(base));
+        fs.push_layer("top", Box::new(top));
+
+        assert_eq!(fs.list("common").unwrap(), vec!["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(fs.read_text("common/a.txt").unwrap(), "base");
+        assert_eq!(fs.read_text("common/b.txt").unwrap(), "top");
+        assert_eq!(fs.origin("common/b.txt"), Some("top".into()));
+    }
+
+    #[test]
+    fn overlay_replace_dir() {
+        let base = MemoryFileSystem::new();
+        base.write_text("history/states/1-Foo.txt", "base", false).unwrap();
+        base.write_text("history/states/2-Bar.txt", "base", false).unwrap();
+        base.write_text("history/units/FOO.txt", "base", false).unwrap();
+        let top = MemoryFileSystem::new();
+        top.write_text("history/states/3-Cheeze.txt", "top", false).unwrap();
+
+        let mut fs = OverlayFileSystem::new();
+        fs.push_layer("base", Box::new(base));
+        fs.push_layer("top", Box::new(top));
+        fs.replace_dir("history/states");
+
+        assert_eq!(fs.list("history/states").unwrap(), vec!["3-Cheeze.txt"]);
+        assert!(!fs.is_file("history/states/1-Foo.txt"));
+        assert!(fs.is_file("history/units/FOO.txt"));
+    }
+}
@@ -0,0 +1,195 @@
+use clausewitz_data::{CwTable, CwKeyValue};
+use ids::Hoi4IdRemap;
+
+/// Keys in a character's definition that give it a role.
+const ROLE_KEYS: &'static [&'static str] = &[
+    "country_leader", "corps_commander", "field_marshal", "navy_leader", "advisor",
+];
+
+/// Keys in history that refer to a character by its ID.
+const REFERENCE_KEYS: &'static [&'static str] = &[
+    "recruit_character", "retire_character", "promote_character", "has_character",
+    "activate_advisor", "deactivate_advisor",
+];
+
+/// A picture of a character, for example the "army" portrait used when they lead troops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4Portrait {
+    pub kind: String,
+    pub large: Option<String>,
+    pub small: Option<String>,
+}
+
+/// Something a character can be, for example "country_leader" or "advisor". The role's data is
+/// kept as it is, it differs a lot between roles.
+#[derive(Debug, Clone)]
+pub struct Hoi4CharacterRole {
+    pub kind: String,
+    pub data: CwTable,
+}
+
+impl Hoi4CharacterRole {
+    pub fn ideology(&self) -> Option<&String> {
+        self.data.get("ideology").and_then(|v| v.as_string())
+    }
+
+    /// Gets the advisor slot, for example "political_advisor".
+    pub fn slot(&self) -> Option<&String> {
+        self.data.get("slot").and_then(|v| v.as_string())
+    }
+
+    pub fn traits(&self) -> Vec<&String> {
+        self.data.get("traits")
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_string()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// A character defined in `common/characters`, countries get them through `recruit_character`
+/// in their history.
+#[derive(Debug, Clone)]
+pub struct Hoi4Character {
+    pub id: String,
+    pub name: Option<String>,
+    pub portraits: Vec<Hoi4Portrait>,
+    pub roles: Vec<Hoi4CharacterRole>,
+    pub other: CwTable,
+}
+
+impl Hoi4Character {
+    pub fn from_table(id: &str, table: &CwTable) -> Self {
+        let mut portraits = Vec::new();
+        if let Some(kinds) = table.get("portraits").and_then(|v| v.as_table()) {
+            for kind in &kinds.values {
+                if let Some(sizes) = kind.value.as_table() {
+                    portraits.push(Hoi4Portrait {
+                        kind: kind.key.clone(),
+                        large: sizes.get("large").and_then(|v| v.as_string()).cloned(),
+                        small: sizes.get("small").and_then(|v| v.as_string()).cloned(),
+                    });
+                }
+            }
+        }
+
+        let roles = table.values.iter()
+            .filter(|v| ROLE_KEYS.contains(&v.key.as_str()))
+            .map(|v| Hoi4CharacterRole {
+                kind: v.key.clone(),
+                data: v.value.as_table().cloned().unwrap_or_else(CwTable::new),
+            })
+            .collect();
+
+        let mut other = CwTable::new();
+        other.values = table.values.iter()
+            .filter(|v| v.key != "name" && v.key != "portraits" && !ROLE_KEYS.contains(&v.key.as_str()))
+            .cloned()
+            .collect();
+
+        Hoi4Character {
+            id: id.into(),
+            name: table.get("name").and_then(|v| v.as_string()).cloned(),
+            portraits: portraits,
+            roles: roles,
+            other: other,
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        if let Some(ref name) = self.name {
+            table.set("name", name.into());
+        }
+
+        if self.portraits.len() != 0 {
+            let mut portraits = CwTable::new();
+            for portrait in &self.portraits {
+                let mut sizes = CwTable::new();
+                if let Some(ref large) = portrait.large {
+                    sizes.set("large", large.into());
+                }
+                if let Some(ref small) = portrait.small {
+                    sizes.set("small", small.into());
+                }
+                portraits.values.push(CwKeyValue::new(portrait.kind.clone(), sizes.into()));
+            }
+            table.set("portraits", portraits.into());
+        }
+
+        for role in &self.roles {
+            table.add(&role.kind, role.data.clone().into());
+        }
+        table.values.extend(self.other.values.iter().cloned());
+
+        table
+    }
+
+    pub fn roles_of(&self, kind: &str) -> Vec<&Hoi4CharacterRole> {
+        self.roles.iter().filter(|r| r.kind == kind).collect()
+    }
+
+    pub fn is_country_leader(&self) -> bool {
+        self.roles.iter().any(|r| r.kind == "country_leader")
+    }
+
+    pub fn is_advisor(&self) -> bool {
+        self.roles.iter().any(|r| r.kind == "advisor")
+    }
+
+    /// Copies the character under a new ID. Advisor roles get new idea tokens based on the new
+    /// ID, since those have to be unique as well, and legacy IDs in roles are replaced.
+    pub fn clone_as(&self, id: &str, remap: &mut Hoi4IdRemap) -> Self {
+        let mut character = self.clone();
+        character.id = id.into();
+
+        let mut advisors = 0;
+        for role in character.roles.iter_mut() {
+            if role.kind == "advisor" && role.data.has_key("idea_token") {
+                let token = if advisors == 0 { id.to_string() } else { format!("{}_{}", id, advisors) };
+                role.data.set("idea_token", token.into());
+                advisors += 1;
+            }
+
+            remap.replace_id(&mut role.data);
+        }
+
+        character
+    }
+}
+
+/// Gets the characters defined in a file in `common/characters`.
+pub fn characters_in(file: &CwTable) -> Vec<Hoi4Character> {
+    file.get_all("characters").into_iter()
+        .filter_map(|v| v.as_table())
+        .flat_map(|t| t.values.iter())
+        .filter_map(|v| v.value.as_table().map(|t| Hoi4Character::from_table(&v.key, t)))
+        .collect()
+}
+
+/// Gets the IDs of all characters referenced in a table, in the order they're first referenced.
+pub fn collect_references<'a>(table: &'a CwTable, ids: &mut Vec<&'a String>) {
+    for entry in &table.values {
+        if let Some(nested) = entry.value.as_table() {
+            collect_references(nested, ids);
+        } else if let Some(id) = entry.value.as_string() {
+            if REFERENCE_KEYS.contains(&entry.key.as_str()) && !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+}
+
+/// Replaces all references to a character in a table.
+pub fn replace_references(table: &mut CwTable, old: &str, new: &str) {
+    for entry in table.values.iter_mut() {
+        let is_reference = REFERENCE_KEYS.contains(&entry.key.as_str());
+        if let Some(nested) = entry.value.as_table_mut() {
+            replace_references(nested, old, new);
+            continue;
+        }
+
+        if is_reference && entry.value.as_string().map(|v| v == old).unwrap_or(false) {
+            entry.value = new.into();
+        }
+    }
+}
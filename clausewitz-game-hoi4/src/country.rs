@@ -0,0 +1,127 @@
+use clausewitz_data::{CwTable, CwValue};
+use history::Hoi4Date;
+
+/// The `set_politics` entry in a country's history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4Politics {
+    pub ruling_party: Option<String>,
+    pub last_election: Option<Hoi4Date>,
+    pub election_frequency: Option<u32>,
+    pub elections_allowed: Option<bool>,
+}
+
+impl Hoi4Politics {
+    pub fn new(ruling_party: &str) -> Self {
+        Hoi4Politics {
+            ruling_party: Some(ruling_party.into()),
+            last_election: None,
+            election_frequency: None,
+            elections_allowed: None,
+        }
+    }
+
+    pub fn from_table(table: &CwTable) -> Self {
+        let string = |key: &str| table.get(key).and_then(|v| v.as_string());
+
+        Hoi4Politics {
+            ruling_party: string("ruling_party").cloned(),
+            last_election: string("last_election").and_then(|v| Hoi4Date::parse(v)),
+            election_frequency: string("election_frequency").and_then(|v| v.parse().ok()),
+            elections_allowed: string("elections_allowed").map(|v| v == "yes"),
+        }
+    }
+
+    /// Writes the values that are set into a table, keeping anything else that's in it.
+    pub fn write_to(&self, table: &mut CwTable) {
+        if let Some(ref party) = self.ruling_party {
+            table.set("ruling_party", party.into());
+        }
+        if let Some(date) = self.last_election {
+            table.set("last_election", date.to_string().into());
+        }
+        if let Some(frequency) = self.election_frequency {
+            table.set("election_frequency", frequency.to_string().into());
+        }
+        if let Some(allowed) = self.elections_allowed {
+            table.set("elections_allowed", yes_no(allowed));
+        }
+    }
+}
+
+/// A `create_country_leader` entry in a country's history.
+#[derive(Debug, Clone)]
+pub struct Hoi4CountryLeader {
+    pub name: String,
+    pub desc: Option<String>,
+    pub picture: Option<String>,
+    pub expire: Option<Hoi4Date>,
+    pub ideology: Option<String>,
+    pub traits: Vec<String>,
+    /// Entries this doesn't know about, written back as they are.
+    pub other: CwTable,
+}
+
+impl Hoi4CountryLeader {
+    pub fn new(name: &str, ideology: &str) -> Self {
+        Hoi4CountryLeader {
+            name: name.into(),
+            desc: None,
+            picture: None,
+            expire: None,
+            ideology: Some(ideology.into()),
+            traits: Vec::new(),
+            other: CwTable::new(),
+        }
+    }
+
+    pub fn from_table(table: &CwTable) -> Self {
+        let string = |key: &str| table.get(key).and_then(|v| v.as_string());
+
+        let mut other = CwTable::new();
+        other.values = table.values.iter()
+            .filter(|v| !LEADER_KEYS.contains(&v.key.as_str()))
+            .cloned()
+            .collect();
+
+        Hoi4CountryLeader {
+            name: string("name").cloned().unwrap_or_default(),
+            desc: string("desc").cloned(),
+            picture: string("picture").cloned(),
+            expire: string("expire").and_then(|v| Hoi4Date::parse(v)),
+            ideology: string("ideology").cloned(),
+            traits: match table.get("traits") {
+                Some(&CwValue::String(ref t)) => vec![t.clone()],
+                Some(&CwValue::Array(ref a)) => a.iter().filter_map(|v| v.as_string().cloned()).collect(),
+                _ => Vec::new(),
+            },
+            other: other,
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+        if let Some(ref desc) = self.desc {
+            table.set("desc", desc.into());
+        }
+        if let Some(ref picture) = self.picture {
+            table.set("picture", picture.into());
+        }
+        if let Some(expire) = self.expire {
+            table.set("expire", expire.to_string().into());
+        }
+        if let Some(ref ideology) = self.ideology {
+            table.set("ideology", ideology.into());
+        }
+        table.set("traits", CwValue::Array(self.traits.iter().map(|t| t.into()).collect()));
+        table.values.extend(self.other.values.iter().cloned());
+
+        table
+    }
+}
+
+const LEADER_KEYS: &'static [&'static str] = &["name", "desc", "picture", "expire", "ideology", "traits"];
+
+pub fn yes_no(value: bool) -> CwValue {
+    if value { "yes".into() } else { "no".into() }
+}
@@ -0,0 +1,193 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind};
+use clausewitz_data::{file, CwTable, CwValue};
+use clausewitz_data::archive::ZipFileSystem;
+use clausewitz_data::vfs::{FileSystem, DiskFileSystem};
+
+/// The contents of a `.mod` descriptor file, as read by the game launcher.
+#[derive(Debug, Clone)]
+pub struct ModDescriptor {
+    pub name: String,
+    pub path: Option<String>,
+    pub archive: Option<String>,
+    pub tags: Vec<String>,
+    pub version: Option<String>,
+    pub supported_version: Option<String>,
+    pub dependencies: Vec<String>,
+    pub replace_path: Vec<String>,
+    pub picture: Option<String>,
+    pub remote_file_id: Option<String>,
+    pub user_dir: Option<String>,
+}
+
+impl ModDescriptor {
+    pub fn new(name: &str) -> Self {
+        ModDescriptor {
+            name: name.into(),
+            path: None,
+            archive: None,
+            tags: Vec::new(),
+            version: None,
+            supported_version: None,
+            dependencies: Vec::new(),
+            replace_path: Vec::new(),
+            picture: None,
+            remote_file_id: None,
+            user_dir: None,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let text = try!(file::read_all_text(path));
+        let table = try!(CwTable::try_parse(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e)));
+        Ok(Self::from_table(&table))
+    }
+
+    pub fn from_table(table: &CwTable) -> Self {
+        let string = |key: &str| table.get(key).and_then(|v| v.as_string()).cloned();
+
+        ModDescriptor {
+            name: string("name").unwrap_or_default(),
+            path: string("path"),
+            archive: string("archive"),
+            tags: string_list(table.get("tags")),
+            version: string("version"),
+            supported_version: string("supported_version"),
+            dependencies: string_list(table.get("dependencies")),
+            // Unlike the other keys, replace_path is given once for every path to replace
+            replace_path: table.values.iter()
+                .filter(|v| v.key.to_lowercase() == "replace_path")
+                .filter_map(|v| v.value.as_string().cloned())
+                .collect(),
+            picture: string("picture"),
+            remote_file_id: string("remote_file_id"),
+            user_dir: string("user_dir"),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+
+        set_optional(&mut table, "path", &self.path);
+        set_optional(&mut table, "archive", &self.archive);
+        if self.tags.len() != 0 {
+            table.set("tags", CwValue::Array(self.tags.iter().map(|t| t.into()).collect()));
+        }
+        set_optional(&mut table, "version", &self.version);
+        set_optional(&mut table, "supported_version", &self.supported_version);
+        if self.dependencies.len() != 0 {
+            table.set("dependencies", CwValue::Array(self.dependencies.iter().map(|d| d.into()).collect()));
+        }
+        for replace_path in &self.replace_path {
+            table.add("replace_path", replace_path.into());
+        }
+        set_optional(&mut table, "picture", &self.picture);
+        set_optional(&mut table, "remote_file_id", &self.remote_file_id);
+        set_optional(&mut table, "user_dir", &self.user_dir);
+
+        table
+    }
+
+    /// Writes the descriptor as the text of a `.mod` file. Unlike `to_table().serialize()`,
+    /// every value is quoted, the launcher and the parser need that for paths and versions like
+    /// "mod/name" or "1.*".
+    pub fn serialize(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.to_table().values {
+            match entry.value {
+                CwValue::Array(ref values) => {
+                    let values: Vec<_> = values.iter().filter_map(|v| v.as_string()).map(|v| quote(v)).collect();
+                    text.push_str(&format!("{} = {{ {} }}\n", entry.key, values.join(" ")));
+                },
+                _ => if let Some(value) = entry.value.as_string() {
+                    text.push_str(&format!("{} = {}\n", entry.key, quote(value)));
+                },
+            }
+        }
+        text
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        file::write_all_text(path, &self.serialize(), false)
+    }
+
+    /// Opens the mod's files, either from its archive or its folder. Both are relative to the
+    /// game's user directory, the one containing the "mod" folder.
+    pub fn open_files<P: AsRef<Path>>(&self, user_dir: P) -> Result<Box<dyn FileSystem>, Error> {
+        let mut location = user_dir.as_ref().to_path_buf();
+
+        if let Some(ref archive) = self.archive {
+            location.push(archive);
+            return Ok(Box::new(try!(ZipFileSystem::open(location))));
+        }
+
+        if let Some(ref path) = self.path {
+            location.push(path);
+            if location.is_dir() {
+                return Ok(Box::new(DiskFileSystem::new(location)));
+            }
+        }
+
+        Err(Error::new(ErrorKind::NotFound, format!("Can't find the files for mod \"{}\"", self.name)))
+    }
+}
+
+fn string_list(value: Option<&CwValue>) -> Vec<String> {
+    match value {
+        Some(&CwValue::String(ref v)) => vec![v.clone()],
+        Some(&CwValue::Array(ref a)) => a.iter().filter_map(|v| v.as_string().cloned()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn set_optional(table: &mut CwTable, key: &str, value: &Option<String>) {
+    if let &Some(ref value) = value {
+        table.set(key, value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::io::ErrorKind;
+    use clausewitz_data::CwTable;
+    use super::ModDescriptor;
+
+    #[test]
+    fn descriptor_roundtrip() {
+        let mut descriptor = ModDescriptor::new("Shattered \"Hearts\"");
+        descriptor.path = Some("mod/shattered_hearts".into());
+        descriptor.supported_version = Some("1.*".into());
+        descriptor.tags = vec!["Alternative History".into(), "Map".into()];
+        descriptor.replace_path = vec!["history/states".into(), "history/countries".into()];
+
+        let text = descriptor.serialize();
+        assert!(text.contains("path = \"mod/shattered_hearts\"\n"));
+        let loaded = ModDescriptor::from_table(&CwTable::try_parse(&text).unwrap());
+        assert_eq!(loaded.name, descriptor.name);
+        assert_eq!(loaded.path, descriptor.path);
+        assert_eq!(loaded.supported_version, descriptor.supported_version);
+        assert_eq!(loaded.tags, descriptor.tags);
+        assert_eq!(loaded.replace_path, descriptor.replace_path);
+
+        let table = CwTable::parse("name = Test Replace_Path = \"history/states\"");
+        assert_eq!(ModDescriptor::from_table(&table).replace_path, vec!["history/states"]);
+    }
+
+    #[test]
+    fn load_broken_descriptor() {
+        let mut file = env::temp_dir();
+        file.push(format!("clausewitz-game-hoi4-broken-{}.mod", process::id()));
+        fs::write(&file, "name = \"Broken\" tags = {").unwrap();
+
+        let error = ModDescriptor::load(&file).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fmt;
+
+/// Something that went wrong while loading game data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hoi4LoadError {
+    MissingDirectory(String),
+    MissingFile(String),
+    UnreadableFile { file: String, message: String },
+    UnparseableFile { file: String, message: String },
+    /// A country tag that doesn't have the files it needs, or points to a file that isn't there.
+    UnmatchedTag { tag: String, message: String },
+    MissingKey { file: String, key: String },
+    /// A mod to load on top of the game whose files can't be found.
+    MissingMod { name: String, message: String },
+}
+
+impl fmt::Display for Hoi4LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Hoi4LoadError::MissingDirectory(ref dir) =>
+                write!(f, "\"{}\" is not an existing directory", dir),
+            &Hoi4LoadError::MissingFile(ref file) =>
+                write!(f, "\"{}\" does not exist", file),
+            &Hoi4LoadError::UnreadableFile { ref file, ref message } =>
+                write!(f, "Could not read \"{}\": {}", file, message),
+            &Hoi4LoadError::UnparseableFile { ref file, ref message } =>
+                write!(f, "Could not parse \"{}\": {}", file, message),
+            &Hoi4LoadError::UnmatchedTag { ref tag, ref message } =>
+                write!(f, "Country tag {}: {}", tag, message),
+            &Hoi4LoadError::MissingKey { ref file, ref key } =>
+                write!(f, "\"{}\" is missing \"{}\"", file, key),
+            &Hoi4LoadError::MissingMod { ref name, ref message } =>
+                write!(f, "Mod \"{}\": {}", name, message),
+        }
+    }
+}
+
+impl Error for Hoi4LoadError {
+    fn description(&self) -> &str {
+        match self {
+            &Hoi4LoadError::MissingDirectory(_) => "missing directory",
+            &Hoi4LoadError::MissingFile(_) => "missing file",
+            &Hoi4LoadError::UnreadableFile { .. } => "unreadable file",
+            &Hoi4LoadError::UnparseableFile { .. } => "unparseable file",
+            &Hoi4LoadError::UnmatchedTag { .. } => "unmatched country tag",
+            &Hoi4LoadError::MissingKey { .. } => "missing key",
+            &Hoi4LoadError::MissingMod { .. } => "missing mod",
+        }
+    }
+}
+
+/// Something that went wrong while exporting a mod.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hoi4ExportError {
+    MissingDirectory(String),
+    UnwritableFile { file: String, message: String },
+    /// A file of a previous export that couldn't be read to compare against.
+    UnreadableFile { file: String, message: String },
+    /// Two different things in the mod were going to be written to the same file.
+    FileConflict(String),
+    InvalidPath(String),
+    /// An existing folder, archive or .mod file in the way of the export that wasn't created by
+    /// exporting the same mod.
+    NotExported(String),
+}
+
+impl fmt::Display for Hoi4ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Hoi4ExportError::MissingDirectory(ref dir) =>
+                write!(f, "\"{}\" is not an existing directory", dir),
+            &Hoi4ExportError::UnwritableFile { ref file, ref message } =>
+                write!(f, "Could not write \"{}\": {}", file, message),
+            &Hoi4ExportError::UnreadableFile { ref file, ref message } =>
+                write!(f, "Could not read \"{}\": {}", file, message),
+            &Hoi4ExportError::FileConflict(ref file) =>
+                write!(f, "\"{}\" is exported more than once", file),
+            &Hoi4ExportError::InvalidPath(ref file) =>
+                write!(f, "\"{}\" is not a relative path inside the mod", file),
+            &Hoi4ExportError::NotExported(ref dir) =>
+                write!(f, "\"{}\" was not created by exporting this mod, refusing to replace it", dir),
+        }
+    }
+}
+
+impl Error for Hoi4ExportError {
+    fn description(&self) -> &str {
+        match self {
+            &Hoi4ExportError::MissingDirectory(_) => "missing directory",
+            &Hoi4ExportError::UnwritableFile { .. } => "unwritable file",
+            &Hoi4ExportError::UnreadableFile { .. } => "unreadable file",
+            &Hoi4ExportError::FileConflict(_) => "file conflict",
+            &Hoi4ExportError::InvalidPath(_) => "invalid path",
+            &Hoi4ExportError::NotExported(_) => "directory not exported",
+        }
+    }
+}
+
+/// A change to loaded data that couldn't be made, because the block it goes in is missing or
+/// isn't a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4EditError {
+    pub file: String,
+    pub key: String,
+}
+
+impl fmt::Display for Hoi4EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Can't change \"{}\", \"{}\" is missing or not a block", self.file, self.key)
+    }
+}
+
+impl Error for Hoi4EditError {
+    fn description(&self) -> &str {
+        "missing block"
+    }
+}
@@ -0,0 +1,219 @@
+//! History files have entries that apply at the start of the game, followed by blocks like
+//! `1939.1.1 = { owner = GER }` that only apply when starting at or after their date.
+
+use std::fmt;
+use clausewitz_data::{CwTable, CwValue, CwKeyValue};
+
+/// Keys that can be given more than once, each one adding to the ones before it instead of
+/// replacing them.
+const ADDITIVE_KEYS: &'static [&'static str] = &[
+    "add_core_of", "add_claim_by", "victory_points", "add_ideas", "set_technology",
+    "create_country_leader", "create_corps_commander", "create_field_marshal", "create_navy_leader",
+    "recruit_character",
+];
+
+/// Keys that undo an additive key, with the key they undo.
+const REMOVING_KEYS: &'static [(&'static str, &'static str)] = &[
+    ("remove_core_of", "add_core_of"),
+    ("remove_claim_by", "add_claim_by"),
+    ("remove_ideas", "add_ideas"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hoi4Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Hoi4Date {
+    pub fn new(year: u32, month: u32, day: u32) -> Self {
+        Hoi4Date {
+            year: year,
+            month: month,
+            day: day,
+        }
+    }
+
+    /// Parses a date in the game's format, for example "1939.1.1".
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts: Option<Vec<u32>> = text.split('.').map(|p| p.parse().ok()).collect();
+        match parts {
+            Some(ref parts) if parts.len() == 3 => Some(Hoi4Date::new(parts[0], parts[1], parts[2])),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Hoi4Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.year, self.month, self.day)
+    }
+}
+
+/// Gets the entries that aren't in a dated block.
+pub fn undated(history: &CwTable) -> CwTable {
+    let mut table = CwTable::new();
+    table.values = history.values.iter()
+        .filter(|v| Hoi4Date::parse(&v.key).is_none())
+        .cloned()
+        .collect();
+    table
+}
+
+/// Gets the dated blocks, sorted by date. Blocks with the same date stay in the order they're in.
+pub fn dated(history: &CwTable) -> Vec<(Hoi4Date, &CwTable)> {
+    let mut blocks: Vec<_> = history.values.iter()
+        .filter_map(|v| Hoi4Date::parse(&v.key).and_then(|d| v.value.as_table().map(|t| (d, t))))
+        .collect();
+    blocks.sort_by_key(|b| b.0);
+    blocks
+}
+
+/// Evaluates what the history looks like when starting the game at a date, with all dated blocks
+/// up to and including that date applied.
+pub fn effective_at(history: &CwTable, date: Hoi4Date) -> CwTable {
+    let mut table = undated(history);
+
+    for (_, block) in dated(history).into_iter().take_while(|b| b.0 <= date) {
+        for entry in &block.values {
+            apply(&mut table, &entry.key, &entry.value);
+        }
+    }
+
+    table
+}
+
+fn apply(table: &mut CwTable, key: &str, value: &CwValue) {
+    let lower = key.to_lowercase();
+    if ADDITIVE_KEYS.contains(&lower.as_str()) {
+        table.values.push(CwKeyValue::new(key, value.clone()));
+        return;
+    }
+
+    if let Some(&(_, undone)) = REMOVING_KEYS.iter().find(|k| k.0 == lower) {
+        remove_values(table, undone, &strings(value));
+        return;
+    }
+
+    // Tables like buildings are changed one value at a time
+    if let (Some(existing), &CwValue::Table(ref changes)) = (table.get_mut(key), value) {
+        if let Some(existing) = existing.as_table_mut() {
+            for change in &changes.values {
+                apply(existing, &change.key, &change.value);
+            }
+            return;
+        }
+    }
+
+    table.set(key, value.clone());
+}
+
+/// Gets the strings in a value that's either a single string or a list of them.
+fn strings(value: &CwValue) -> Vec<&String> {
+    match value {
+        &CwValue::String(ref v) => vec![v],
+        &CwValue::Array(ref a) => a.iter().filter_map(|v| v.as_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Removes values from all entries with a key, like ideas from `add_ideas = { a b }`. Entries left
+/// without any values are removed entirely.
+fn remove_values(table: &mut CwTable, key: &str, removed: &[&String]) {
+    for entry in table.values.iter_mut().filter(|v| v.key.to_lowercase() == key) {
+        if let CwValue::Array(ref mut values) = entry.value {
+            values.retain(|v| v.as_string().map(|v| !removed.contains(&v)).unwrap_or(true));
+        }
+    }
+
+    table.values.retain(|v| v.key.to_lowercase() != key || match v.value {
+        CwValue::String(ref v) => !removed.contains(&v),
+        CwValue::Array(ref a) => a.len() != 0,
+        _ => true,
+    });
+}
+
+/// Sets a value at the top level and in every dated block that also sets it, so the value is the
+/// same no matter which date the game is started at.
+pub fn set_everywhere(history: &mut CwTable, key: &str, value: CwValue) {
+    history.set(key, value.clone());
+
+    for entry in history.values.iter_mut().filter(|v| Hoi4Date::parse(&v.key).is_some()) {
+        if let Some(block) = entry.value.as_table_mut() {
+            if block.has_key(key) {
+                block.set(key, value.clone());
+            }
+        }
+    }
+}
+
+/// Removes all dated blocks, leaving only what applies at the start of the game.
+pub fn strip_dated(history: &mut CwTable) {
+    history.values.retain(|v| Hoi4Date::parse(&v.key).is_none());
+}
+
+/// Replaces the history with what it looks like at a date, without any dated blocks left.
+pub fn flatten_at(history: &mut CwTable, date: Hoi4Date) {
+    *history = effective_at(history, date);
+}
+
+#[cfg(test)]
+mod tests {
+    use clausewitz_data::CwTable;
+    use super::{Hoi4Date, dated, effective_at, set_everywhere};
+
+    const HISTORY: &'static str = "owner = FRA add_core_of = FRA buildings = { infrastructure = 4 } \
+        1940.5.1 = { controller = GER } \
+        1939.1.1 = { owner = GER add_core_of = GER remove_core_of = FRA buildings = { arms_factory = 1 } }";
+
+    #[test]
+    fn remove_from_lists() {
+        let history = CwTable::parse("add_ideas = { a b } Add_Ideas = c \
+            1939.1.1 = { Remove_Ideas = { a c } } 1940.1.1 = { remove_ideas = b }");
+
+        let later = effective_at(&history, Hoi4Date::new(1939, 1, 1));
+        let ideas = later.get_all("add_ideas");
+        assert_eq!(ideas.len(), 1);
+        assert_eq!(ideas[0].as_array().unwrap().len(), 1);
+        assert_eq!(ideas[0].as_array().unwrap()[0].as_string().unwrap(), "b");
+
+        let latest = effective_at(&history, Hoi4Date::new(1940, 1, 1));
+        assert!(!latest.has_key("add_ideas"));
+    }
+
+    #[test]
+    fn parse_date() {
+        assert_eq!(Hoi4Date::parse("1939.1.1"), Some(Hoi4Date::new(1939, 1, 1)));
+        assert_eq!(Hoi4Date::parse("owner"), None);
+        assert_eq!(Hoi4Date::parse("1.2"), None);
+        assert_eq!(Hoi4Date::new(1936, 1, 1).to_string(), "1936.1.1");
+    }
+
+    #[test]
+    fn effective_history() {
+        let history = CwTable::parse(HISTORY);
+        assert_eq!(dated(&history)[0].0, Hoi4Date::new(1939, 1, 1));
+
+        let start = effective_at(&history, Hoi4Date::new(1936, 1, 1));
+        assert_eq!(start.get("owner").unwrap().as_string().unwrap(), "FRA");
+        assert!(!start.has_key("1939.1.1"));
+
+        let later = effective_at(&history, Hoi4Date::new(1939, 1, 1));
+        assert_eq!(later.get("owner").unwrap().as_string().unwrap(), "GER");
+        assert_eq!(later.get_all("add_core_of").len(), 1);
+        assert!(!later.has_key("controller"));
+        let buildings = later.get("buildings").unwrap().as_table().unwrap();
+        assert_eq!(buildings.values.len(), 2);
+    }
+
+    #[test]
+    fn set_in_dated_blocks() {
+        let mut history = CwTable::parse(HISTORY);
+        set_everywhere(&mut history, "owner", "ITA".into());
+
+        let later = effective_at(&history, Hoi4Date::new(1941, 1, 1));
+        assert_eq!(later.get("owner").unwrap().as_string().unwrap(), "ITA");
+        assert_eq!(later.get("controller").unwrap().as_string().unwrap(), "GER");
+    }
+}
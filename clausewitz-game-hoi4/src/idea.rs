@@ -0,0 +1,135 @@
+use clausewitz_data::CwTable;
+
+/// An idea defined in `common/ideas`, national spirits and advisors are both ideas.
+#[derive(Debug, Clone)]
+pub struct Hoi4Idea {
+    pub id: String,
+    /// The group the idea is in, for example "country" for national spirits.
+    pub category: String,
+    /// Conditions for a country to be able to have the idea.
+    pub allowed: Option<CwTable>,
+    /// Political power needed to remove the idea, -1 if it can't be removed.
+    pub removal_cost: Option<f64>,
+    pub picture: Option<String>,
+    pub modifier: CwTable,
+    /// Modifiers applying only towards another country, each with a `tag`.
+    pub targeted_modifiers: Vec<CwTable>,
+    /// Entries this doesn't know about, written back as they are.
+    pub other: CwTable,
+}
+
+impl Hoi4Idea {
+    pub fn new(id: &str, category: &str) -> Self {
+        Hoi4Idea {
+            id: id.into(),
+            category: category.into(),
+            allowed: None,
+            removal_cost: None,
+            picture: None,
+            modifier: CwTable::new(),
+            targeted_modifiers: Vec::new(),
+            other: CwTable::new(),
+        }
+    }
+
+    pub fn from_table(id: &str, category: &str, table: &CwTable) -> Self {
+        let mut idea = Hoi4Idea::new(id, category);
+        for entry in &table.values {
+            match entry.key.as_str() {
+                "allowed" => idea.allowed = Some(entry.value.as_table().cloned().unwrap_or_else(CwTable::new)),
+                "removal_cost" => idea.removal_cost = entry.value.as_string().and_then(|v| v.parse().ok()),
+                "picture" => idea.picture = entry.value.as_string().cloned(),
+                "modifier" => if let Some(modifier) = entry.value.as_table() {
+                    idea.modifier = modifier.clone();
+                },
+                "targeted_modifier" => if let Some(modifier) = entry.value.as_table() {
+                    idea.targeted_modifiers.push(modifier.clone());
+                },
+                _ => idea.other.values.push(entry.clone()),
+            }
+        }
+        idea
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        if let Some(ref allowed) = self.allowed {
+            table.set("allowed", allowed.clone().into());
+        }
+        if let Some(cost) = self.removal_cost {
+            table.set("removal_cost", cost.to_string().into());
+        }
+        if let Some(ref picture) = self.picture {
+            table.set("picture", picture.into());
+        }
+        if self.modifier.values.len() != 0 {
+            table.set("modifier", self.modifier.clone().into());
+        }
+        for modifier in &self.targeted_modifiers {
+            table.add("targeted_modifier", modifier.clone().into());
+        }
+        table.values.extend(self.other.values.iter().cloned());
+
+        table
+    }
+}
+
+/// Gets the ideas defined in a file in `common/ideas`.
+pub fn ideas_in(file: &CwTable) -> Vec<Hoi4Idea> {
+    let mut ideas = Vec::new();
+    for categories in file.get_all("ideas").into_iter().filter_map(|v| v.as_table()) {
+        for category in &categories.values {
+            if let Some(table) = category.value.as_table() {
+                ideas.extend(table.values.iter()
+                    .filter_map(|v| v.value.as_table().map(|t| Hoi4Idea::from_table(&v.key, &category.key, t))));
+            }
+        }
+    }
+    ideas
+}
+
+/// Writes ideas as the contents of a file in `common/ideas`, grouped by category.
+pub fn ideas_file(ideas: &[Hoi4Idea]) -> CwTable {
+    let mut categories = CwTable::new();
+    for idea in ideas {
+        if let Some(category) = categories.get_table_or_insert(&idea.category) {
+            category.add(&idea.id, idea.to_table().into());
+        }
+    }
+
+    let mut file = CwTable::new();
+    file.set("ideas", categories.into());
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use clausewitz_data::CwTable;
+    use super::{Hoi4Idea, ideas_in, ideas_file};
+
+    #[test]
+    fn idea_roundtrip() {
+        let file = CwTable::parse("ideas = { country = { fractured = { \
+            allowed = { always = yes } removal_cost = -1 picture = generic_intel_bonus \
+            modifier = { justify_war_goal_time = -0.99 } \
+            targeted_modifier = { tag = FRA attack_bonus_against = 0.1 } \
+            cancel = { has_war = yes } } } }");
+        let ideas = ideas_in(&file);
+        assert_eq!(ideas.len(), 1);
+        assert_eq!(ideas[0].category, "country");
+        assert_eq!(ideas[0].removal_cost, Some(-1.0));
+        assert_eq!(ideas[0].targeted_modifiers.len(), 1);
+        assert!(ideas[0].other.has_key("cancel"));
+
+        let mut spirit = Hoi4Idea::new("spirit", "country");
+        spirit.picture = Some("generic_morale_bonus".into());
+        let mut advisor = Hoi4Idea::new("advisor", "political_advisor");
+        advisor.removal_cost = Some(10.0);
+
+        let ideas = ideas_in(&ideas_file(&[ideas[0].clone(), spirit, advisor]));
+        let ids: Vec<_> = ideas.iter().map(|i| (i.category.as_str(), i.id.as_str())).collect();
+        assert_eq!(ids, vec![("country", "fractured"), ("country", "spirit"), ("political_advisor", "advisor")]);
+        assert_eq!(ideas[0].modifier.get("justify_war_goal_time").unwrap().as_string().unwrap(), "-0.99");
+        assert_eq!(ideas[1].picture, Some("generic_morale_bonus".into()));
+    }
+}
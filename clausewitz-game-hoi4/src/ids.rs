@@ -0,0 +1,172 @@
+//! Giving the leaders in copied history new IDs, since the game requires them to be unique across
+//! all countries.
+
+use std::collections::HashMap;
+use clausewitz_data::{CwTable, CwValue};
+
+/// Blocks that create something with an ID, anywhere in history files.
+const DEFINITION_KEYS: &'static [&'static str] = &[
+    "create_corps_commander", "create_field_marshal", "create_navy_leader", "create_country_leader",
+    "create_operative_leader",
+];
+
+/// Blocks that always need an ID, even if the original didn't have one.
+const REQUIRED_ID_KEYS: &'static [&'static str] = &[
+    "create_corps_commander", "create_field_marshal", "create_navy_leader",
+];
+
+/// Keys holding the ID of the thing defined by their table. Character roles keep the ID leaders
+/// had before characters existed as `legacy_id`, which units still refer to.
+const ID_KEYS: &'static [&'static str] = &["id", "legacy_id"];
+
+/// Keys that refer to the ID of something created elsewhere, for example a fleet's admiral.
+const REFERENCE_KEYS: &'static [&'static str] = &[
+    "leader", "admiral", "commander", "unit_leader", "remove_unit_leader", "has_unit_leader",
+];
+
+/// Hands out IDs that haven't been used yet, shared between everything that gets new IDs.
+pub struct Hoi4IdAllocator {
+    next: u32,
+}
+
+impl Hoi4IdAllocator {
+    /// Creates an allocator handing out IDs starting at `first`, which should be higher than any
+    /// ID used by the game or other mods.
+    pub fn new(first: u32) -> Self {
+        Hoi4IdAllocator {
+            next: first,
+        }
+    }
+
+    pub fn next_id(&mut self) -> u32 {
+        self.next += 1;
+        self.next - 1
+    }
+
+    /// Starts replacing the IDs in a copy of some data, the same old ID will be replaced by the
+    /// same new ID everywhere the remap is used.
+    pub fn remap<'a>(&'a mut self) -> Hoi4IdRemap<'a> {
+        Hoi4IdRemap {
+            allocator: self,
+            ids: HashMap::new(),
+        }
+    }
+}
+
+pub struct Hoi4IdRemap<'a> {
+    allocator: &'a mut Hoi4IdAllocator,
+    ids: HashMap<u32, u32>,
+}
+
+impl<'a> Hoi4IdRemap<'a> {
+    /// Gets the new ID for something being defined, allocating one if it's the first time.
+    pub fn define(&mut self, old: Option<u32>) -> u32 {
+        match old {
+            Some(old) => {
+                if let Some(&new) = self.ids.get(&old) {
+                    return new;
+                }
+
+                let new = self.allocator.next_id();
+                self.ids.insert(old, new);
+                new
+            },
+            None => self.allocator.next_id(),
+        }
+    }
+
+    /// Gets the new ID for an old ID that was defined in this remap.
+    pub fn get(&self, old: u32) -> Option<u32> {
+        self.ids.get(&old).cloned()
+    }
+
+    /// Replaces the `id` and `legacy_id` in a table that defines a single thing, if it has them.
+    pub fn replace_id(&mut self, table: &mut CwTable) {
+        for key in ID_KEYS {
+            let old = table.get(key).and_then(|v| v.as_string()).and_then(|v| v.parse().ok());
+            if old.is_some() {
+                let new = self.define(old);
+                table.set(key, new.to_string().into());
+            }
+        }
+    }
+
+    /// Replaces the IDs of everything defined in a table, and then all references to them.
+    pub fn apply(&mut self, table: &mut CwTable) {
+        self.apply_definitions(table);
+        self.apply_references(table);
+    }
+
+    fn apply_definitions(&mut self, table: &mut CwTable) {
+        for entry in table.values.iter_mut() {
+            let is_definition = DEFINITION_KEYS.contains(&entry.key.as_str());
+            let id_required = REQUIRED_ID_KEYS.contains(&entry.key.as_str());
+
+            if let Some(nested) = entry.value.as_table_mut() {
+                if is_definition && !nested.has_key("id") && id_required {
+                    let new = self.define(None);
+                    nested.set("id", new.to_string().into());
+                } else if is_definition {
+                    self.replace_id(nested);
+                } else {
+                    self.apply_definitions(nested);
+                }
+            }
+        }
+    }
+
+    fn apply_references(&mut self, table: &mut CwTable) {
+        for entry in table.values.iter_mut() {
+            let is_reference = REFERENCE_KEYS.contains(&entry.key.as_str());
+
+            match &mut entry.value {
+                &mut CwValue::String(ref mut id) if is_reference => {
+                    if let Some(new) = id.parse().ok().and_then(|old| self.get(old)) {
+                        *id = new.to_string();
+                    }
+                },
+                &mut CwValue::Table(ref mut nested) => self.apply_references(nested),
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clausewitz_data::CwTable;
+    use super::Hoi4IdAllocator;
+
+    #[test]
+    fn remap_ids() {
+        let mut history = CwTable::parse("create_field_marshal = { name = \"Gamelin\" id = 50 } \
+            create_corps_commander = { name = \"Georges\" } \
+            1939.1.1 = { create_navy_leader = { name = \"Darlan\" id = 51 } } \
+            create_country_leader = { name = \"Lebrun\" } \
+            if = { limit = { has_unit_leader = 51 } remove_unit_leader = 51 } \
+            remove_unit_leader = 99");
+        let mut units = CwTable::parse("units = { fleet = { name = \"Marine\" admiral = 51 } }");
+
+        let mut allocator = Hoi4IdAllocator::new(1000);
+        {
+            let mut remap = allocator.remap();
+            remap.apply(&mut history);
+            remap.apply(&mut units);
+        }
+        assert_eq!(allocator.next_id(), 1003);
+
+        let id = |t: &CwTable, key: &str| t.get(key).unwrap().as_table().unwrap().get("id")
+            .map(|v| v.as_string().unwrap().clone());
+        assert_eq!(id(&history, "create_field_marshal"), Some("1000".into()));
+        assert_eq!(id(&history, "create_corps_commander"), Some("1001".into()));
+        assert_eq!(id(&history, "create_country_leader"), None);
+        assert_eq!(id(history.get("1939.1.1").unwrap().as_table().unwrap(), "create_navy_leader"),
+            Some("1002".into()));
+
+        let condition = history.get("if").unwrap().as_table().unwrap();
+        assert_eq!(condition.get("remove_unit_leader").unwrap().as_string().unwrap(), "1002");
+        assert_eq!(history.get("remove_unit_leader").unwrap().as_string().unwrap(), "99");
+        let fleet = units.get("units").unwrap().as_table().unwrap().get("fleet").unwrap().as_table().unwrap();
+        assert_eq!(fleet.get("admiral").unwrap().as_string().unwrap(), "1002");
+    }
+}
@@ -1,12 +1,51 @@
 #[macro_use] extern crate log;
+extern crate num_cpus;
 extern crate clausewitz_data;
 
+mod character;
+mod country;
+mod descriptor;
+mod error;
+pub mod history;
+mod idea;
+mod ids;
+mod map;
 mod modif;
+mod oob;
+mod parallel;
+mod tags;
 
-pub use modif::Hoi4Mod;
+pub use character::{Hoi4Character, Hoi4CharacterRole, Hoi4Portrait};
+pub use country::{Hoi4Politics, Hoi4CountryLeader};
+pub use descriptor::ModDescriptor;
+pub use error::{Hoi4LoadError, Hoi4ExportError, Hoi4EditError};
+pub use history::Hoi4Date;
+pub use idea::Hoi4Idea;
+pub use ids::{Hoi4IdAllocator, Hoi4IdRemap};
+pub use map::{Hoi4Map, Hoi4Province, Hoi4Adjacency, Hoi4StrategicRegion, Hoi4SupplyArea};
+pub use modif::{Hoi4Mod, Hoi4ExportDiff, Hoi4ModFile, Hoi4FileContent, Hoi4FileEncoding};
+pub use oob::{Hoi4GridSlot, Hoi4DivisionTemplate, Hoi4Division, Hoi4Fleet, Hoi4TaskForce, Hoi4Ship,
+    Hoi4AirWing, Hoi4ProductionLine};
+pub use tags::Hoi4TagAction;
 
-use std::path::PathBuf;
-use clausewitz_data::{file, CwTable, CwValue};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use clausewitz_data::{CwTable, CwValue, CwKeyValue, CwSource};
+use clausewitz_data::cache::ParseCache;
+use clausewitz_data::localisation::CwLocalisation;
+use clausewitz_data::vfs::{self, FileSystem, DiskFileSystem, OverlayFileSystem};
+
+/// The languages the game has localisation for.
+pub const LANGUAGES: &'static [&'static str] = &[
+    "english", "french", "german", "polish", "russian", "spanish", "braz_por", "japanese", "simp_chinese",
+];
+
+/// The ideologies in the base game, countries can have names specific to each of them.
+pub const IDEOLOGIES: &'static [&'static str] = &["democratic", "communism", "fascism", "neutrality"];
+
+/// The app ID Hearts of Iron IV is distributed under on Steam.
+pub const HOI4_STEAM_APP_ID: u32 = 394360;
 
 #[derive(Clone)]
 pub struct Hoi4Country {
@@ -14,6 +53,7 @@ pub struct Hoi4Country {
     name: String,
     common: CwTable,
     history: CwTable,
+    dynamic: bool,
 }
 
 impl Hoi4Country {
@@ -23,18 +63,14 @@ impl Hoi4Country {
             name: name,
             common: common,
             history: history,
+            dynamic: false,
         }
     }
 
-    pub fn replace_ids<F: FnMut() -> i32>(&mut self, mut id_provider: F) {
-        for ref mut keyval in self.history.values.iter_mut() {
-            if  keyval.key == "create_corps_commander" ||
-                keyval.key == "create_field_marshal" ||
-                keyval.key == "create_navy_leader" {
-                let table = keyval.value.as_table_mut().unwrap();
-                table.set("id", id_provider().into());
-            }
-        }
+    /// Gives the leaders created in the country's history new IDs, including in dated blocks,
+    /// and updates the references to them. Use the same remap for the country's units after.
+    pub fn replace_ids(&mut self, remap: &mut Hoi4IdRemap) {
+        remap.apply(&mut self.history);
     }
 
     pub fn tag(&self) -> &String {
@@ -45,6 +81,11 @@ impl Hoi4Country {
         self.tag = value
     }
 
+    /// Checks if this is a country for a dynamic tag, these don't have any history.
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
     pub fn name(&self) -> &String {
         &self.name
     }
@@ -53,6 +94,28 @@ impl Hoi4Country {
         self.name = value
     }
 
+    /// Gets the name of the country as shown in the game, using the name for the ruling party's
+    /// ideology if there is one.
+    pub fn display_name<'a>(&self, localisation: &'a CwLocalisation, language: &str) -> Option<&'a String> {
+        self.localised(localisation, language, "")
+    }
+
+    /// Gets the adjective for the country, like "French".
+    pub fn adjective<'a>(&self, localisation: &'a CwLocalisation, language: &str) -> Option<&'a String> {
+        self.localised(localisation, language, "_ADJ")
+    }
+
+    /// Gets the name of the country as used in the middle of a sentence, like "the United Kingdom".
+    pub fn definite_name<'a>(&self, localisation: &'a CwLocalisation, language: &str) -> Option<&'a String> {
+        self.localised(localisation, language, "_DEF")
+    }
+
+    fn localised<'a>(&self, localisation: &'a CwLocalisation, language: &str, suffix: &str) -> Option<&'a String> {
+        let ideology = self.politics().and_then(|p| p.ruling_party);
+        ideology.and_then(|i| localisation.get(language, &format!("{}_{}{}", self.tag, i, suffix)))
+            .or_else(|| localisation.get(language, &format!("{}{}", self.tag, suffix)))
+    }
+
     pub fn common_table(&self) -> &CwTable {
         &self.common
     }
@@ -61,22 +124,80 @@ impl Hoi4Country {
         &self.history
     }
 
+    /// Gets where a top-level value in the country's common file was defined.
+    pub fn common_source(&self, key: &str) -> Option<&CwSource> {
+        self.common.source_of(key)
+    }
+
+    /// Gets where a top-level value in the country's history file was defined.
+    pub fn history_source(&self, key: &str) -> Option<&CwSource> {
+        self.history.source_of(key)
+    }
+
     pub fn set_color(&mut self, r: u8, g: u8, b: u8) {
         self.common.set("color", CwValue::from_color(r, g, b));
     }
 
+    /// Gets the country's history as it is when starting the game at a date.
+    pub fn history_at(&self, date: Hoi4Date) -> CwTable {
+        history::effective_at(&self.history, date)
+    }
+
+    /// Removes the dated blocks from the country's history, so every start date uses the same.
+    pub fn strip_dated_history(&mut self) {
+        history::strip_dated(&mut self.history);
+    }
+
+    /// Replaces the country's history with what it is at a date, without any dated blocks.
+    pub fn flatten_history_at(&mut self, date: Hoi4Date) {
+        history::flatten_at(&mut self.history, date);
+    }
+
     pub fn set_capital(&mut self, state: String) {
-        self.history.set("capital", state.into());
+        history::set_everywhere(&mut self.history, "capital", state.into());
     }
 
-    pub fn units(&self) -> &String {
-        self.history.get("oob").unwrap().as_string().unwrap()
+    pub fn units(&self) -> Option<&String> {
+        self.history.get("oob").and_then(|v| v.as_string())
     }
 
     pub fn set_units(&mut self, value: String) {
         self.history.set("oob", value.into());
     }
 
+    /// Gets the IDs of all order of battle files the country's history loads, including the
+    /// separate naval and air ones that are often only loaded with certain DLC.
+    pub fn oob_ids(&self) -> Vec<&String> {
+        let mut ids = Vec::new();
+        collect_oob_ids(&self.history, &mut ids);
+        ids
+    }
+
+    /// Calls the callback with the effect and tag of every reference to another tag in the
+    /// country's common and history data, to keep, remap or drop it. Blocks scoped to a tag are
+    /// passed as the effect "scope".
+    pub fn rewrite_tag_references<F: FnMut(&str, &str) -> Hoi4TagAction>(&mut self, mut f: F) {
+        tags::rewrite_tag_references(&mut self.common, &mut f);
+        tags::rewrite_tag_references(&mut self.history, &mut f);
+    }
+
+    /// Gets the IDs of the characters the country's history recruits or otherwise refers to.
+    pub fn character_ids(&self) -> Vec<&String> {
+        let mut ids = Vec::new();
+        character::collect_references(&self.history, &mut ids);
+        ids
+    }
+
+    /// Replaces every reference to a character in the country's history.
+    pub fn replace_character(&mut self, old: &str, new: &str) {
+        character::replace_references(&mut self.history, old, new);
+    }
+
+    /// Replaces every reference to an order of battle file in the country's history.
+    pub fn replace_oob(&mut self, old: &str, new: &str) {
+        replace_oob_ids(&mut self.history, old, new);
+    }
+
     pub fn add_idea(&mut self, value: String) {
         // Make sure the ideas key actually exists
         if !self.history.has_key("add_ideas") {
@@ -87,13 +208,153 @@ impl Hoi4Country {
 
         // Make sure it's not a single string value only
         if let &mut CwValue::String(_) = ideas_v {
-            *ideas_v = CwValue::Array(Vec::new());
+            let idea = ideas_v.clone();
+            *ideas_v = CwValue::Array(vec![idea]);
         }
 
         // Finally, get the table and add the idea
         let ideas = ideas_v.as_array_mut().unwrap();
         ideas.push(value.into());
     }
+
+    /// Gets the ideas the country starts with, from all of its add_ideas entries.
+    pub fn ideas(&self) -> Vec<&String> {
+        let mut ideas = Vec::new();
+        for value in self.history.get_all("add_ideas") {
+            match value {
+                &CwValue::String(ref idea) => ideas.push(idea),
+                &CwValue::Array(ref a) => ideas.extend(a.iter().filter_map(|v| v.as_string())),
+                _ => {},
+            }
+        }
+        ideas
+    }
+
+    pub fn remove_idea(&mut self, idea: &str) {
+        let idea = idea.to_lowercase();
+        let is_idea = |v: &CwValue| v.as_string().map(|i| i.to_lowercase() == idea).unwrap_or(false);
+        for entry in self.history.values.iter_mut().filter(|v| v.key.to_lowercase() == "add_ideas") {
+            if let Some(ideas) = entry.value.as_array_mut() {
+                ideas.retain(|v| !is_idea(v));
+            }
+        }
+
+        self.history.values.retain(|v| !(v.key.to_lowercase() == "add_ideas" &&
+            (is_idea(&v.value) || v.value.as_array().map(|a| a.len() == 0).unwrap_or(false))));
+    }
+
+    pub fn politics(&self) -> Option<Hoi4Politics> {
+        self.history.get("set_politics").and_then(|v| v.as_table()).map(Hoi4Politics::from_table)
+    }
+
+    /// Sets the values in the country's politics, anything in the entry that isn't in
+    /// `Hoi4Politics` is kept.
+    pub fn set_politics(&mut self, politics: &Hoi4Politics) {
+        if let Some(table) = self.history.get_table_or_insert("set_politics") {
+            politics.write_to(table);
+            return;
+        }
+
+        // Replace a value that isn't a table at all
+        let mut table = CwTable::new();
+        politics.write_to(&mut table);
+        self.history.set("set_politics", table.into());
+    }
+
+    /// Gets the popularity of every ideology, for example ("fascism", 20.0).
+    pub fn popularities(&self) -> Vec<(String, f64)> {
+        self.history.get("set_popularities")
+            .and_then(|v| v.as_table())
+            .map(|p| number_entries(p))
+            .unwrap_or_default()
+    }
+
+    pub fn set_popularities(&mut self, popularities: &[(String, f64)]) {
+        let mut table = CwTable::new();
+        for &(ref ideology, popularity) in popularities {
+            table.set(ideology, popularity.to_string().into());
+        }
+        self.history.set("set_popularities", table.into());
+    }
+
+    /// Gets the technologies the country starts with researched, from all set_technology entries.
+    pub fn technologies(&self) -> Vec<&String> {
+        self.history.get_all("set_technology").into_iter()
+            .filter_map(|v| v.as_table())
+            .flat_map(|t| t.values.iter().filter(|v| v.value.as_string().map(|s| s == "1").unwrap_or(false)))
+            .map(|v| &v.key)
+            .collect()
+    }
+
+    pub fn add_technology(&mut self, technology: &str) {
+        if self.technologies().iter().any(|t| t.to_lowercase() == technology.to_lowercase()) {
+            return;
+        }
+
+        match self.history.get_table_or_insert("set_technology") {
+            Some(table) => table.set(technology, "1".into()),
+            None => {
+                let mut table = CwTable::new();
+                table.set(technology, "1".into());
+                self.history.add("set_technology", table.into());
+            },
+        }
+    }
+
+    pub fn remove_technology(&mut self, technology: &str) {
+        for entry in self.history.values.iter_mut().filter(|v| v.key.to_lowercase() == "set_technology") {
+            if let Some(table) = entry.value.as_table_mut() {
+                table.remove_all(technology);
+            }
+        }
+    }
+
+    pub fn research_slots(&self) -> Option<u32> {
+        self.history.get("set_research_slots").and_then(number)
+    }
+
+    pub fn set_research_slots(&mut self, slots: u32) {
+        self.history.set("set_research_slots", slots.to_string().into());
+    }
+
+    pub fn convoys(&self) -> Option<u32> {
+        self.history.get("set_convoys").and_then(number)
+    }
+
+    pub fn set_convoys(&mut self, convoys: u32) {
+        self.history.set("set_convoys", convoys.to_string().into());
+    }
+
+    pub fn stability(&self) -> Option<f64> {
+        self.history.get("set_stability").and_then(number)
+    }
+
+    pub fn set_stability(&mut self, stability: f64) {
+        self.history.set("set_stability", stability.to_string().into());
+    }
+
+    pub fn war_support(&self) -> Option<f64> {
+        self.history.get("set_war_support").and_then(number)
+    }
+
+    pub fn set_war_support(&mut self, war_support: f64) {
+        self.history.set("set_war_support", war_support.to_string().into());
+    }
+
+    pub fn country_leaders(&self) -> Vec<Hoi4CountryLeader> {
+        self.history.get_all("create_country_leader").into_iter()
+            .filter_map(|v| v.as_table())
+            .map(Hoi4CountryLeader::from_table)
+            .collect()
+    }
+
+    pub fn add_country_leader(&mut self, leader: &Hoi4CountryLeader) {
+        self.history.add("create_country_leader", leader.to_table().into());
+    }
+
+    pub fn remove_country_leaders(&mut self) {
+        self.history.remove_all("create_country_leader");
+    }
 }
 
 #[derive(Clone)]
@@ -118,12 +379,28 @@ impl Hoi4State {
         &self.data
     }
 
-    fn state_table(&self) -> &CwTable {
-        self.data.get("state").unwrap().as_table().unwrap()
+    /// Gets the first key the state needs that's missing from its data, if any.
+    fn missing_key(&self) -> Option<&'static str> {
+        let state = match self.data.get("state").and_then(|v| v.as_table()) {
+            Some(state) => state,
+            None => return Some("state"),
+        };
+
+        for key in &["id", "name"] {
+            if state.get(key).and_then(|v| v.as_string()).is_none() {
+                return Some(key);
+            }
+        }
+
+        match state.get("history").and_then(|v| v.as_table()) {
+            Some(history) if history.get("owner").and_then(|v| v.as_string()).is_some() => None,
+            Some(_) => Some("history.owner"),
+            None => Some("history"),
+        }
     }
 
-    fn state_table_mut(&mut self) -> &mut CwTable {
-        self.data.get_mut("state").unwrap().as_table_mut().unwrap()
+    fn state_table(&self) -> &CwTable {
+        self.data.get("state").unwrap().as_table().unwrap()
     }
 
 
@@ -131,35 +408,321 @@ impl Hoi4State {
         self.state_table().get("history").unwrap().as_table().unwrap()
     }
 
-    fn history_table_mut(&mut self) -> &mut CwTable {
-        self.state_table_mut().get_mut("history").unwrap().as_table_mut().unwrap()
-    }
-
     pub fn name(&self) -> &String {
         self.state_table().get("name").unwrap().as_string().unwrap()
     }
 
-    pub fn owner(&self) -> &String {
-        self.history_table().get("owner").unwrap().as_string().unwrap()
+    pub fn owner(&self) -> Option<&String> {
+        self.try_history_table().and_then(|h| h.get("owner")).and_then(|v| v.as_string())
     }
 
-    pub fn set_owner(&mut self, tag: String) {
-        self.history_table_mut().set("owner", tag.into());
+    /// Gets where a value in the state's history was defined, for example "owner".
+    pub fn history_source(&self, key: &str) -> Option<&CwSource> {
+        self.history_table().source_of(key)
     }
 
-    pub fn set_controller(&mut self, tag: String) {
-        self.history_table_mut().set("controller", tag.into());
+    /// Gets the state's history as it is when starting the game at a date.
+    pub fn history_at(&self, date: Hoi4Date) -> Option<CwTable> {
+        self.try_history_table().map(|h| history::effective_at(h, date))
     }
 
-    pub fn add_core(&mut self, tag: String) {
-        self.history_table_mut().add("add_core_of", tag.into());
+    pub fn owner_at(&self, date: Hoi4Date) -> Option<String> {
+        self.history_at(date).and_then(|h| h.get("owner").and_then(|v| v.as_string()).cloned())
     }
 
-    pub fn id(&self) -> &String {
-        self.state_table().get("id").unwrap().as_string().unwrap()
+    /// Removes the dated blocks from the state's history, so every start date uses the same.
+    pub fn strip_dated_history(&mut self) -> Result<(), Hoi4EditError> {
+        history::strip_dated(try!(self.try_history_table_mut()));
+        Ok(())
+    }
+
+    /// Replaces the state's history with what it is at a date, without any dated blocks.
+    pub fn flatten_history_at(&mut self, date: Hoi4Date) -> Result<(), Hoi4EditError> {
+        history::flatten_at(try!(self.try_history_table_mut()), date);
+        Ok(())
+    }
+
+    /// Sets the owner, including in dated blocks that change it.
+    pub fn set_owner(&mut self, tag: String) -> Result<(), Hoi4EditError> {
+        history::set_everywhere(try!(self.try_history_table_mut()), "owner", tag.into());
+        Ok(())
+    }
+
+    /// Sets the controller, including in dated blocks that change it.
+    pub fn set_controller(&mut self, tag: String) -> Result<(), Hoi4EditError> {
+        history::set_everywhere(try!(self.try_history_table_mut()), "controller", tag.into());
+        Ok(())
+    }
+
+    pub fn add_core(&mut self, tag: String) -> Result<(), Hoi4EditError> {
+        try!(self.try_history_table_mut()).add("add_core_of", tag.into());
+        Ok(())
+    }
+
+    pub fn id(&self) -> Option<&String> {
+        self.try_state_table().and_then(|s| s.get("id")).and_then(|v| v.as_string())
+    }
+
+    fn try_state_table(&self) -> Option<&CwTable> {
+        self.data.get("state").and_then(|v| v.as_table())
+    }
+
+    fn try_history_table(&self) -> Option<&CwTable> {
+        self.try_state_table().and_then(|s| s.get("history")).and_then(|v| v.as_table())
+    }
+
+    fn try_state_table_mut(&mut self) -> Result<&mut CwTable, Hoi4EditError> {
+        let file = self.file_name.clone();
+        self.data.get_mut("state").and_then(|v| v.as_table_mut())
+            .ok_or(Hoi4EditError { file: file, key: "state".into() })
+    }
+
+    /// Gets the history table, adding it if it's missing.
+    fn try_history_table_mut(&mut self) -> Result<&mut CwTable, Hoi4EditError> {
+        let file = self.file_name.clone();
+        try!(self.try_state_table_mut()).get_table_or_insert("history")
+            .ok_or(Hoi4EditError { file: file, key: "history".into() })
+    }
+
+    pub fn provinces(&self) -> Option<Vec<u32>> {
+        self.try_state_table()
+            .and_then(|s| s.get("provinces"))
+            .and_then(|v| number_list(v))
+    }
+
+    pub fn set_provinces(&mut self, provinces: &[u32]) -> Result<(), Hoi4EditError> {
+        let provinces = CwValue::Array(provinces.iter().map(|p| p.to_string().into()).collect());
+        try!(self.try_state_table_mut()).set("provinces", provinces);
+        Ok(())
+    }
+
+    pub fn manpower(&self) -> Option<u64> {
+        self.try_state_table().and_then(|s| s.get("manpower")).and_then(|v| number(v))
+    }
+
+    pub fn set_manpower(&mut self, manpower: u64) -> Result<(), Hoi4EditError> {
+        try!(self.try_state_table_mut()).set("manpower", manpower.to_string().into());
+        Ok(())
+    }
+
+    pub fn state_category(&self) -> Option<&String> {
+        self.try_state_table().and_then(|s| s.get("state_category")).and_then(|v| v.as_string())
+    }
+
+    pub fn set_state_category(&mut self, category: &str) -> Result<(), Hoi4EditError> {
+        try!(self.try_state_table_mut()).set("state_category", category.into());
+        Ok(())
+    }
+
+    /// Gets the amounts of all resources in the state, for example ("steel", 12.0).
+    pub fn resources(&self) -> Vec<(String, f64)> {
+        self.try_state_table()
+            .and_then(|s| s.get("resources"))
+            .and_then(|v| v.as_table())
+            .map(|r| number_entries(r))
+            .unwrap_or_default()
+    }
+
+    pub fn resource(&self, resource: &str) -> Option<f64> {
+        self.try_state_table()
+            .and_then(|s| s.get("resources"))
+            .and_then(|v| v.as_table())
+            .and_then(|r| r.get(resource))
+            .and_then(|v| number(v))
+    }
+
+    pub fn set_resource(&mut self, resource: &str, amount: f64) -> Result<(), Hoi4EditError> {
+        let file = self.file_name.clone();
+        let resources = try!(try!(self.try_state_table_mut()).get_table_or_insert("resources")
+            .ok_or(Hoi4EditError { file: file, key: "resources".into() }));
+        resources.set(resource, amount.to_string().into());
+        Ok(())
+    }
+
+    /// Gets the levels of the state-level buildings, for example ("industrial_complex", 2).
+    pub fn buildings(&self) -> Vec<(String, u32)> {
+        // Province-level buildings are in the same table, keyed by province ID
+        self.try_history_table()
+            .and_then(|h| h.get("buildings"))
+            .and_then(|v| v.as_table())
+            .map(|b| number_entries(b))
+            .unwrap_or_default()
+    }
+
+    pub fn building(&self, building: &str) -> Option<u32> {
+        self.buildings().into_iter().find(|&(ref k, _)| k == building).map(|(_, l)| l)
+    }
+
+    pub fn set_building(&mut self, building: &str, level: u32) -> Result<(), Hoi4EditError> {
+        try!(self.try_buildings_table_mut()).set(building, level.to_string().into());
+        Ok(())
+    }
+
+    /// Gets the levels of the buildings in one of the state's provinces, like naval bases.
+    pub fn province_buildings(&self, province: u32) -> Vec<(String, u32)> {
+        self.try_history_table()
+            .and_then(|h| h.get("buildings"))
+            .and_then(|v| v.as_table())
+            .and_then(|b| b.get(&province.to_string()))
+            .and_then(|v| v.as_table())
+            .map(|b| number_entries(b))
+            .unwrap_or_default()
+    }
+
+    pub fn set_province_building(&mut self, province: u32, building: &str, level: u32)
+        -> Result<(), Hoi4EditError> {
+        let file = self.file_name.clone();
+        let province = province.to_string();
+        let buildings = try!(try!(self.try_buildings_table_mut()).get_table_or_insert(&province)
+            .ok_or(Hoi4EditError { file: file, key: format!("history.buildings.{}", province) }));
+        buildings.set(building, level.to_string().into());
+        Ok(())
+    }
+
+    fn try_buildings_table_mut(&mut self) -> Result<&mut CwTable, Hoi4EditError> {
+        let file = self.file_name.clone();
+        try!(self.try_history_table_mut()).get_table_or_insert("buildings")
+            .ok_or(Hoi4EditError { file: file, key: "history.buildings".into() })
+    }
+
+    /// Gets the victory points in the state as (province, value) pairs.
+    pub fn victory_points(&self) -> Vec<(u32, f64)> {
+        // Every province with victory points has its own victory_points entry
+        self.try_history_table()
+            .map(|h| h.get_all("victory_points"))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v.as_array())
+            .filter_map(|a| match (a.get(0).and_then(number), a.get(1).and_then(number)) {
+                (Some(province), Some(value)) => Some((province, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets the victory points of a province, replacing its existing entry if there is one.
+    pub fn set_victory_points(&mut self, province: u32, value: f64) -> Result<(), Hoi4EditError> {
+        let history = try!(self.try_history_table_mut());
+        let entry = CwValue::Array(vec![province.to_string().into(), value.to_string().into()]);
+
+        let existing = history.values.iter_mut()
+            .filter(|v| v.key.to_lowercase() == "victory_points")
+            .find(|v| v.value.as_array().and_then(|a| a.get(0)).and_then(number) == Some(province));
+        match existing {
+            Some(existing) => {
+                existing.value = entry;
+                existing.source = None;
+            },
+            None => history.add("victory_points", entry),
+        }
+
+        Ok(())
+    }
+
+    /// Calls the callback with the effect and tag of every tag reference in the state, including
+    /// its owner, cores and claims, to keep, remap or drop it.
+    pub fn rewrite_tag_references<F: FnMut(&str, &str) -> Hoi4TagAction>(&mut self, mut f: F) {
+        tags::rewrite_tag_references(&mut self.data, &mut f);
+    }
+
+    /// Gets the name of the state as shown in the game.
+    pub fn display_name<'a>(&self, localisation: &'a CwLocalisation, language: &str) -> Option<&'a String> {
+        self.try_state_table()
+            .and_then(|s| s.get("name"))
+            .and_then(|v| v.as_string())
+            .and_then(|key| localisation.get(language, key))
+    }
+
+    /// Gets the names of the state's provinces with victory points, as shown in the game.
+    pub fn victory_point_names<'a>(&self, localisation: &'a CwLocalisation, language: &str) -> Vec<(u32, &'a String)> {
+        self.victory_points().into_iter()
+            .filter_map(|(p, _)| localisation.get(language, &format!("VICTORY_POINTS_{}", p)).map(|n| (p, n)))
+            .collect()
+    }
+
+    /// Gets the tags of the countries that have a core on the state.
+    pub fn cores(&self) -> Vec<&String> {
+        self.history_strings("add_core_of")
+    }
+
+    /// Gets the tags of the countries that have a claim on the state.
+    pub fn claims(&self) -> Vec<&String> {
+        self.history_strings("add_claim_by")
+    }
+
+    pub fn add_claim(&mut self, tag: String) -> Result<(), Hoi4EditError> {
+        try!(self.try_history_table_mut()).add("add_claim_by", tag.into());
+        Ok(())
+    }
+
+    fn history_strings(&self, key: &str) -> Vec<&String> {
+        self.try_history_table()
+            .map(|h| h.get_all(key).into_iter().filter_map(|v| v.as_string()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn is_impassable(&self) -> bool {
+        self.try_state_table()
+            .and_then(|s| s.get("impassable"))
+            .and_then(|v| v.as_string())
+            .map(|v| v == "yes")
+            .unwrap_or(false)
+    }
+
+    pub fn set_impassable(&mut self, impassable: bool) -> Result<(), Hoi4EditError> {
+        let state = try!(self.try_state_table_mut());
+        if impassable {
+            state.set("impassable", "yes".into());
+        } else {
+            state.remove_all("impassable");
+        }
+        Ok(())
     }
 }
 
+const OOB_KEYS: &'static [&'static str] = &["oob", "set_oob", "set_naval_oob", "set_air_oob", "load_oob"];
+
+fn collect_oob_ids<'a>(table: &'a CwTable, ids: &mut Vec<&'a String>) {
+    for entry in &table.values {
+        match &entry.value {
+            &CwValue::String(ref id) if OOB_KEYS.contains(&entry.key.as_str()) => {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            },
+            &CwValue::Table(ref t) => collect_oob_ids(t, ids),
+            _ => {},
+        }
+    }
+}
+
+fn replace_oob_ids(table: &mut CwTable, old: &str, new: &str) {
+    for entry in table.values.iter_mut() {
+        let is_oob = OOB_KEYS.contains(&entry.key.as_str());
+        match &mut entry.value {
+            &mut CwValue::String(ref mut id) if is_oob && id == old => *id = new.into(),
+            &mut CwValue::Table(ref mut t) => replace_oob_ids(t, old, new),
+            _ => {},
+        }
+    }
+}
+
+fn number<T: FromStr>(value: &CwValue) -> Option<T> {
+    value.as_string().and_then(|v| v.parse().ok())
+}
+
+/// Parses an array of numbers, fails if any of them isn't a number.
+fn number_list<T: FromStr>(value: &CwValue) -> Option<Vec<T>> {
+    value.as_array().and_then(|a| a.iter().map(number).collect())
+}
+
+/// Gets all entries in a table that have a number as their value.
+fn number_entries<T: FromStr>(table: &CwTable) -> Vec<(String, T)> {
+    table.values.iter()
+        .filter_map(|v| number(&v.value).map(|n| (v.key.clone(), n)))
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct Hoi4Units {
     id: String,
@@ -186,106 +749,444 @@ impl Hoi4Units {
         self.id = id;
     }
 
+    /// Updates references to leaders, like fleet admirals, to the IDs they were given when
+    /// replacing the IDs of the country that owns the units.
+    pub fn replace_ids(&mut self, remap: &mut Hoi4IdRemap) {
+        remap.apply(&mut self.data);
+    }
+
     pub fn clear(&mut self) {
         self.data.set("units", CwTable::new().into());
         self.data.set("air_wings", CwTable::new().into());
         self.data.set("instant_effect", CwTable::new().into());
     }
+
+    pub fn division_templates(&self) -> Vec<Hoi4DivisionTemplate> {
+        oob::tables(&self.data, "division_template").into_iter()
+            .map(Hoi4DivisionTemplate::from_table)
+            .collect()
+    }
+
+    pub fn set_division_templates(&mut self, templates: &[Hoi4DivisionTemplate]) {
+        self.data.remove_all("division_template");
+        for template in templates {
+            self.add_division_template(template);
+        }
+    }
+
+    pub fn add_division_template(&mut self, template: &Hoi4DivisionTemplate) {
+        self.data.add("division_template", template.to_table().into());
+    }
+
+    pub fn divisions(&self) -> Vec<Hoi4Division> {
+        self.units_table()
+            .map(|u| oob::tables(u, "division").into_iter().map(Hoi4Division::from_table).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_divisions(&mut self, divisions: &[Hoi4Division]) {
+        let units = self.units_table_mut();
+        units.remove_all("division");
+        for division in divisions {
+            units.add("division", division.to_table().into());
+        }
+    }
+
+    pub fn add_division(&mut self, division: &Hoi4Division) {
+        self.units_table_mut().add("division", division.to_table().into());
+    }
+
+    pub fn fleets(&self) -> Vec<Hoi4Fleet> {
+        self.units_table()
+            .map(|u| oob::tables(u, "fleet").into_iter().map(Hoi4Fleet::from_table).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_fleets(&mut self, fleets: &[Hoi4Fleet]) {
+        let units = self.units_table_mut();
+        units.remove_all("fleet");
+        for fleet in fleets {
+            units.add("fleet", fleet.to_table().into());
+        }
+    }
+
+    pub fn air_wings(&self) -> Vec<Hoi4AirWing> {
+        let air_wings = match self.data.get("air_wings").and_then(|v| v.as_table()) {
+            Some(air_wings) => air_wings,
+            None => return Vec::new(),
+        };
+
+        let mut wings = Vec::new();
+        for state in &air_wings.values {
+            let (id, equipment) = match (state.key.parse(), state.value.as_table()) {
+                (Ok(id), Some(equipment)) => (id, equipment),
+                _ => continue,
+            };
+
+            for entry in &equipment.values {
+                if let Some(table) = entry.value.as_table() {
+                    wings.push(Hoi4AirWing::from_table(id, &entry.key, table));
+                }
+            }
+        }
+        wings
+    }
+
+    pub fn set_air_wings(&mut self, wings: &[Hoi4AirWing]) {
+        let mut air_wings = CwTable::new();
+        for wing in wings {
+            let state = air_wings.get_table_or_insert(&wing.state.to_string()).unwrap();
+            state.add(&wing.equipment, wing.to_table().into());
+        }
+        self.data.set("air_wings", air_wings.into());
+    }
+
+    /// Gets the production lines the country starts with.
+    pub fn production_lines(&self) -> Vec<Hoi4ProductionLine> {
+        self.data.get("instant_effect")
+            .and_then(|v| v.as_table())
+            .map(|e| oob::tables(e, "add_equipment_production").into_iter().map(Hoi4ProductionLine::from_table).collect())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the production lines, other instant effects are kept.
+    pub fn set_production_lines(&mut self, lines: &[Hoi4ProductionLine]) {
+        let effect = match self.data.get_table_or_insert("instant_effect") {
+            Some(effect) => effect,
+            None => return,
+        };
+
+        effect.remove_all("add_equipment_production");
+        for line in lines {
+            effect.add("add_equipment_production", line.to_table().into());
+        }
+    }
+
+    /// Removes all units that don't start in the given provinces, and air wings that aren't
+    /// stationed in the given states. Division templates and production lines are kept.
+    pub fn retain_located_in(&mut self, provinces: &[u32], states: &[u32]) {
+        let divisions: Vec<_> = self.divisions().into_iter()
+            .filter(|d| provinces.contains(&d.location))
+            .collect();
+        let fleets: Vec<_> = self.fleets().into_iter()
+            .filter(|f| f.naval_base.map(|b| provinces.contains(&b)).unwrap_or(false))
+            .collect();
+        let air_wings: Vec<_> = self.air_wings().into_iter()
+            .filter(|w| states.contains(&w.state))
+            .collect();
+
+        self.set_divisions(&divisions);
+        self.set_fleets(&fleets);
+        self.set_air_wings(&air_wings);
+    }
+
+    fn units_table(&self) -> Option<&CwTable> {
+        self.data.get("units").and_then(|v| v.as_table())
+    }
+
+    fn units_table_mut(&mut self) -> &mut CwTable {
+        // Replace anything that isn't a table, there are no units we could keep in there
+        if self.data.get_table_or_insert("units").is_none() {
+            self.data.set("units", CwTable::new().into());
+        }
+        self.data.get_mut("units").unwrap().as_table_mut().unwrap()
+    }
+}
+
+/// Settings for how game data is loaded.
+#[derive(Clone, Default)]
+pub struct Hoi4LoadOptions {
+    /// If given, used to skip parsing files that haven't changed since the last load.
+    pub cache: Option<ParseCache>,
+    /// If set, problems that only affect a single file or country are collected as warnings and
+    /// the affected data is skipped, rather than failing the entire load.
+    pub collect_warnings: bool,
+    /// If set, the province map is loaded as well. This takes a while, so only do it if needed.
+    pub load_map: bool,
 }
 
 pub struct CwGameHoi4 {
     countries: Vec<Hoi4Country>,
+    dynamic_countries: Vec<Hoi4Country>,
     states: Vec<Hoi4State>,
     units: Vec<Hoi4Units>,
+    characters: Vec<Hoi4Character>,
+    ideas: Vec<Hoi4Idea>,
+    localisation: CwLocalisation,
+    map: Option<Hoi4Map>,
+    warnings: Vec<Hoi4LoadError>,
 }
 
 impl CwGameHoi4 {
-    pub fn at(path: &PathBuf) -> Self {
+    /// Loads the game data in the game's install directory.
+    pub fn at(path: &PathBuf, options: &Hoi4LoadOptions) -> Result<Self, Hoi4LoadError> {
+        Self::load(Arc::new(DiskFileSystem::new(path.clone())), options)
+    }
+
+    /// Loads the game data in the game's install directory with mods applied on top of it, in
+    /// order. Mod locations are relative to the user directory, the one containing "mod".
+    pub fn at_with_mods(
+        path: &PathBuf, mods: &[ModDescriptor], user_dir: &Path, options: &Hoi4LoadOptions
+    ) -> Result<Self, Hoi4LoadError> {
+        let fs = try!(Self::layered_file_system(path, mods, user_dir));
+        Self::load(Arc::new(fs), options)
+    }
+
+    /// Creates a file system showing the game's files as they are with the mods applied, the
+    /// same way the game itself combines them.
+    pub fn layered_file_system(
+        path: &PathBuf, mods: &[ModDescriptor], user_dir: &Path
+    ) -> Result<OverlayFileSystem, Hoi4LoadError> {
+        let mut fs = OverlayFileSystem::new();
+        fs.push_layer("game", Box::new(DiskFileSystem::new(path.clone())));
+
+        for descriptor in mods {
+            info!("Adding mod \"{}\"...", descriptor.name);
+            let files = try!(descriptor.open_files(user_dir).map_err(|e| Hoi4LoadError::MissingMod {
+                name: descriptor.name.clone(),
+                message: e.to_string(),
+            }));
+
+            fs.push_layer(&descriptor.name, files);
+            for dir in &descriptor.replace_path {
+                fs.replace_dir(dir);
+            }
+        }
+
+        Ok(fs)
+    }
+
+    /// Loads the game data from a file system, which can be any source of files laid out like
+    /// the game's install directory.
+    pub fn load(fs: Arc<dyn FileSystem>, options: &Hoi4LoadOptions) -> Result<Self, Hoi4LoadError> {
+        let mut issues = Issues::new(options);
+
         // Load in data related to countries
-        let country_histories = Self::load_directory(path, "history", "countries");
-        let country_commons = Self::load_directory(path, "common", "countries");
+        let country_histories = try!(Self::load_directory(&fs, "history/countries", &mut issues));
+        let country_commons = try!(Self::load_directory(&fs, "common/countries", &mut issues));
+
+        // Load in the country tag mapping, mods can add tags in files of their own so we need
+        // all of them, tags defined in later files replace earlier ones
+        let country_tag_files = try!(Self::load_directory(&fs, "common/country_tags", &mut issues));
+        let mut country_tags: Vec<(CwKeyValue, bool)> = Vec::new();
+        for (_, data) in country_tag_files {
+            // Everything after dynamic_tags are tags the game assigns to countries created while
+            // playing, these don't have history of their own
+            let mut dynamic = false;
+            for key_value in data.values {
+                if key_value.key == "dynamic_tags" {
+                    dynamic = key_value.value.as_string().map(|v| v == "yes").unwrap_or(false);
+                    continue;
+                }
 
-        // Load in the country tag mapping
-        let mut country_tags_file = path.clone();
-        country_tags_file.push("common/country_tags/00_countries.txt");
-        let text = file::read_all_text(&country_tags_file).unwrap();
-        let country_tags_data = CwTable::parse(&text);
-        let country_tags = country_tags_data.values.iter()
-            .map(|v| (v.key.clone(), v.value.as_string().unwrap()));
+                match country_tags.iter_mut().find(|v| v.0.key == key_value.key) {
+                    Some(existing) => *existing = (key_value, dynamic),
+                    None => country_tags.push((key_value, dynamic)),
+                }
+            }
+        }
 
         // Load in the countries
         let mut countries = Vec::new();
-        for country_tag in country_tags {
-            // When we reach this we're done with the file
-            if country_tag.0 == "dynamic_tags" {
-                break;
-            }
+        let mut dynamic_countries = Vec::new();
+        for &(ref country_tag, dynamic) in &country_tags {
+            let tag = &country_tag.key;
+            debug!("Combining common and history for {}...", tag);
 
-            debug!("Combining common and history for {}...", country_tag.0);
+            // Find the common file this tag points to
+            let common_file = match country_tag.value.as_string() {
+                Some(file) => vfs::join("common", file),
+                None => {
+                    try!(issues.add(Hoi4LoadError::UnmatchedTag {
+                        tag: tag.clone(),
+                        message: "Does not point to a country file".into(),
+                    }));
+                    continue;
+                }
+            };
+            let common = match country_commons.iter().find(|c| vfs::join("common/countries", &c.0) == common_file) {
+                Some(common) => common.1.clone(),
+                None if fs.is_file(&common_file) => {
+                    // Not directly in the countries directory, but it does exist
+                    let mut common = try!(load_file(&*fs, &common_file, options.cache.as_ref()));
+                    common.set_source(&common_file, Some(&fs.origin(&common_file).unwrap_or("game".into())));
+                    common
+                },
+                None => {
+                    try!(issues.add(Hoi4LoadError::UnmatchedTag {
+                        tag: tag.clone(),
+                        message: format!("Points to \"{}\" which does not exist", common_file),
+                    }));
+                    continue;
+                }
+            };
 
-            // Split the target file into sections then merge it back to extract the name
             // This isn't localization related, this is just the name the files use to refer to it
-            let file_name_segments: Vec<_> = country_tag.1
-                .split(|c| c == '/' || c == ' ' || c == '-' || c == '.')
-                .filter(|s| s.len() != 0 && s != &"txt")
-                .skip(1)
-                .collect();
-            let mut segment_iter = file_name_segments.iter();
-            let mut country_name = String::new();
-            country_name.push_str(segment_iter.next().unwrap());
-            for segment in segment_iter {
-                country_name.push(' ');
-                country_name.push_str(&segment);
-            }
+            let country_name = country_file_name(&common_file);
 
-            // Find the common file for this country
-            let common = country_commons.iter()
-                .find(|c| c.0.contains(&country_name)).unwrap().clone();
+            if dynamic {
+                let mut country = Hoi4Country::load(tag.clone(), country_name, common, CwTable::new());
+                country.dynamic = true;
+                dynamic_countries.push(country);
+                continue;
+            }
 
-            // Find the history file associated with this tag
-            let history = country_histories.iter()
-                .find(|h| h.0.starts_with(&country_tag.0)).unwrap().clone();
+            // Find the history file, these are named "TAG - Name.txt" after the common file. Some
+            // aren't, so fall back to another one for the tag but say which one was picked
+            let exact_name = format!("{} - {}.txt", tag, country_name);
+            let mut history = country_histories.iter().find(|h| h.0 == exact_name);
+            if history.is_none() {
+                history = country_histories.iter().find(|h| history_file_tag(&h.0) == Some(tag.as_str()));
+                if let Some(history) = history {
+                    issues.note(Hoi4LoadError::UnmatchedTag {
+                        tag: tag.clone(),
+                        message: format!("Expected \"history/countries/{}\", using \"history/countries/{}\" instead",
+                            exact_name, history.0),
+                    });
+                }
+            }
 
-            countries.push(Hoi4Country::load(country_tag.0, country_name, common.1, history.1));
+            match history {
+                Some(history) => countries.push(Hoi4Country::load(
+                    tag.clone(), country_name, common, history.1.clone()
+                )),
+                None => try!(issues.add(Hoi4LoadError::UnmatchedTag {
+                    tag: tag.clone(),
+                    message: format!("No history file found, expected \"history/countries/{}\"", exact_name),
+                })),
+            }
         }
 
-        // Load in the states
-        let states = Self::load_directory(path, "history", "states").into_iter()
-            .map(|t| Hoi4State::load(t.0, t.1)).collect();
+        // Load in the states, making sure they have what we need to work with them
+        let mut states = Vec::new();
+        for (file_name, data) in try!(Self::load_directory(&fs, "history/states", &mut issues)) {
+            let state = Hoi4State::load(file_name, data);
+            match state.missing_key() {
+                Some(key) => try!(issues.add(Hoi4LoadError::MissingKey {
+                    file: format!("history/states/{}", state.file_name()),
+                    key: key.into(),
+                })),
+                None => states.push(state),
+            }
+        }
 
         // Load in the units files
-        let units = Self::load_directory(path, "history", "units").into_iter()
+        let units = try!(Self::load_directory(&fs, "history/units", &mut issues)).into_iter()
             .map(|t| Hoi4Units::load(t.0, t.1)).collect();
 
+        // Load in the characters, older versions of the game don't have these so they're optional
+        let mut characters: Vec<Hoi4Character> = Vec::new();
+        if fs.is_dir("common/characters") {
+            for (_, data) in try!(Self::load_directory(&fs, "common/characters", &mut issues)) {
+                for character in character::characters_in(&data) {
+                    // Characters defined again in later files replace the earlier ones
+                    match characters.iter_mut().find(|c| c.id == character.id) {
+                        Some(existing) => *existing = character,
+                        None => characters.push(character),
+                    }
+                }
+            }
+        }
+
+        // Load in the ideas, these are grouped by category in the files
+        let mut ideas: Vec<Hoi4Idea> = Vec::new();
+        if fs.is_dir("common/ideas") {
+            for (_, data) in try!(Self::load_directory(&fs, "common/ideas", &mut issues)) {
+                for idea in idea::ideas_in(&data) {
+                    match ideas.iter_mut().find(|i| i.id == idea.id) {
+                        Some(existing) => *existing = idea,
+                        None => ideas.push(idea),
+                    }
+                }
+            }
+        }
+
         // Create the container type holding all the data
-        CwGameHoi4 {
+        Ok(CwGameHoi4 {
             countries: countries,
+            dynamic_countries: dynamic_countries,
             states: states,
             units: units,
+            characters: characters,
+            ideas: ideas,
+            localisation: try!(Self::load_localisation(&*fs, &mut issues)),
+            map: if options.load_map { Some(try!(Hoi4Map::load(&*fs))) } else { None },
+            warnings: issues.warnings,
+        })
+    }
+
+    /// Loads the localisation for all languages. Newer versions of the game keep these in a
+    /// directory per language, older versions directly in the localisation directory, both with
+    /// a "replace" directory that takes priority.
+    fn load_localisation(fs: &dyn FileSystem, issues: &mut Issues) -> Result<CwLocalisation, Hoi4LoadError> {
+        let mut dirs = vec![String::from("localisation")];
+        for language in LANGUAGES {
+            dirs.push(format!("localisation/{}", language));
         }
+        let replace_dirs: Vec<_> = dirs.iter().map(|d| format!("{}/replace", d)).collect();
+        dirs.extend(replace_dirs);
+
+        let mut localisation = CwLocalisation::new();
+        for dir in dirs.iter().filter(|d| fs.is_dir(d)) {
+            let file_names = try!(fs.list(dir).map_err(|_| Hoi4LoadError::MissingDirectory(dir.clone())));
+            for file_name in file_names.iter().filter(|f| f.ends_with(".yml")) {
+                let file = vfs::join(dir, file_name);
+                debug!("Loading {}...", file);
+
+                let result = fs.read_text(&file)
+                    .map_err(|e| Hoi4LoadError::UnreadableFile { file: file.clone(), message: e.to_string() })
+                    .and_then(|text| localisation.add_file(&text)
+                        .map_err(|e| Hoi4LoadError::UnparseableFile { file: file.clone(), message: e }));
+                if let Err(error) = result {
+                    try!(issues.add(error));
+                }
+            }
+        }
+
+        Ok(localisation)
     }
 
-    fn load_directory(path: &PathBuf, sub1: &str, sub2: &str) -> Vec<(String, CwTable)> {
-        // Get the root of the states directory
-        let mut dir = path.clone();
-        dir.push(sub1);
-        dir.push(sub2);
-        assert!(dir.is_dir(), "\"{}\" is not an existing directory", dir.display());
+    fn load_directory(
+        fs: &Arc<dyn FileSystem>, dir: &str, issues: &mut Issues
+    ) -> Result<Vec<(String, CwTable)>, Hoi4LoadError> {
+        if !fs.is_dir(dir) {
+            return Err(Hoi4LoadError::MissingDirectory(dir.into()));
+        }
+
+        // Find all the files in that directory, these are sorted so the result doesn't depend on
+        // the file system's ordering
+        let file_names = try!(fs.list(dir).map_err(|_| Hoi4LoadError::MissingDirectory(dir.into())));
 
-        // Go over all the files in that directory
-        let mut states = Vec::new();
-        for file_r in dir.read_dir().unwrap() {
-            let file = file_r.unwrap();
-            let file_name = String::from(file.file_name().to_str().unwrap());
-            debug!("Loading {}/{}/{}...", sub1, sub2, file_name);
+        // Parse all the files in parallel
+        let fs = fs.clone();
+        let dir = String::from(dir);
+        let cache = issues.options.cache.clone();
+        let results = parallel::map_ordered(file_names, move |file_name| {
+            let relative = vfs::join(&dir, &file_name);
+            debug!("Loading {}...", relative);
+
+            let mut file_data = try!(load_file(&*fs, &relative, cache.as_ref()));
+            let layer = fs.origin(&relative).unwrap_or("game".into());
+            file_data.set_source(&relative, Some(&layer));
+
+            Ok((file_name, file_data))
+        });
 
-            // Load in the table
-            let text = file::read_all_text(&file.path()).unwrap();
-            let file_data = CwTable::parse(&text);
-            states.push((file_name, file_data));
+        // Files that failed to load are skipped, as long as we're collecting warnings
+        let mut files = Vec::new();
+        for result in results {
+            match result {
+                Ok(file) => files.push(file),
+                Err(error) => try!(issues.add(error)),
+            }
         }
 
-        states
+        Ok(files)
+    }
+
+    /// Gets the problems found while loading, if warnings were collected instead of failing.
+    pub fn warnings(&self) -> &Vec<Hoi4LoadError> {
+        &self.warnings
     }
 
     pub fn states(&self) -> &Vec<Hoi4State> {
@@ -296,11 +1197,642 @@ impl CwGameHoi4 {
         &self.countries
     }
 
+    /// Gets the countries for dynamic tags, the game uses these for countries created while
+    /// playing such as civil war break-aways.
+    pub fn dynamic_countries(&self) -> &Vec<Hoi4Country> {
+        &self.dynamic_countries
+    }
+
     pub fn country_for_tag(&self, tag: &str) -> Option<&Hoi4Country> {
         self.countries.iter().find(|c| c.tag() == tag)
     }
 
+    /// Checks if a tag is already used, either by a normal or a dynamic country.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.countries.iter().chain(self.dynamic_countries.iter()).any(|c| c.tag() == tag)
+    }
+
     pub fn units_for_id(&self, id: &str) -> Option<&Hoi4Units> {
         self.units.iter().find(|u| u.id() == id)
     }
+
+    pub fn localisation(&self) -> &CwLocalisation {
+        &self.localisation
+    }
+
+    /// Gets the province map, if it was loaded.
+    pub fn map(&self) -> Option<&Hoi4Map> {
+        self.map.as_ref()
+    }
+
+    /// Gets the name of a province, only provinces with victory points have one.
+    pub fn province_name(&self, province: u32, language: &str) -> Option<&String> {
+        self.localisation.get(language, &format!("VICTORY_POINTS_{}", province))
+    }
+
+    pub fn characters(&self) -> &Vec<Hoi4Character> {
+        &self.characters
+    }
+
+    pub fn character(&self, id: &str) -> Option<&Hoi4Character> {
+        self.characters.iter().find(|c| c.id == id)
+    }
+
+    pub fn ideas(&self) -> &Vec<Hoi4Idea> {
+        &self.ideas
+    }
+
+    pub fn idea(&self, id: &str) -> Option<&Hoi4Idea> {
+        self.ideas.iter().find(|i| i.id == id)
+    }
+
+    /// Gets all characters referred to by a country's history that exist.
+    pub fn characters_for_country(&self, country: &Hoi4Country) -> Vec<&Hoi4Character> {
+        country.character_ids().into_iter().filter_map(|id| self.character(id)).collect()
+    }
+
+    /// Gets all order of battle files loaded by a country's history that exist.
+    pub fn units_for_country(&self, country: &Hoi4Country) -> Vec<&Hoi4Units> {
+        country.oob_ids().into_iter().filter_map(|id| self.units_for_id(id)).collect()
+    }
+}
+
+/// Keeps track of non-fatal problems while loading, either failing on them or collecting them.
+struct Issues<'a> {
+    options: &'a Hoi4LoadOptions,
+    warnings: Vec<Hoi4LoadError>,
+}
+
+impl<'a> Issues<'a> {
+    fn new(options: &'a Hoi4LoadOptions) -> Self {
+        Issues {
+            options: options,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, error: Hoi4LoadError) -> Result<(), Hoi4LoadError> {
+        if !self.options.collect_warnings {
+            return Err(error);
+        }
+
+        warn!("{}", error);
+        self.warnings.push(error);
+        Ok(())
+    }
+
+    /// Reports a problem that could be worked around, these don't fail loading even when
+    /// warnings aren't collected.
+    fn note(&mut self, error: Hoi4LoadError) {
+        warn!("{}", error);
+        if self.options.collect_warnings {
+            self.warnings.push(error);
+        }
+    }
+}
+
+/// Gets the name of a country from the path of its common file, "countries/France.txt" gives
+/// "France".
+fn country_file_name(path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if file_name.ends_with(".txt") {
+        file_name[..file_name.len()-4].into()
+    } else {
+        file_name.into()
+    }
+}
+
+/// Gets the tag a country history file is for, "FRA - France.txt" gives "FRA".
+fn history_file_tag(file_name: &str) -> Option<&str> {
+    file_name.find('-').map(|i| file_name[..i].trim())
+}
+
+fn load_file(fs: &dyn FileSystem, path: &str, cache: Option<&ParseCache>) -> Result<CwTable, Hoi4LoadError> {
+    // The same file can be in several layers, each of those needs its own entry. Files that
+    // haven't changed since they were cached don't need to be read at all
+    let name = format!("{}:{}", fs.origin(path).unwrap_or("game".into()), path);
+    let (size, mtime) = (fs.size(path), fs.modified(path).unwrap_or(0));
+    if let (Some(cache), Some(size)) = (cache, size) {
+        if let Some(table) = cache.get(&name, size, mtime) {
+            return Ok(table);
+        }
+    }
+
+    let text = try!(fs.read_text(path).map_err(|e| Hoi4LoadError::UnreadableFile {
+        file: path.into(),
+        message: e.to_string(),
+    }));
+
+    let result = match cache {
+        Some(cache) => cache.parse(&name, &text, size.unwrap_or(text.len() as u64), mtime),
+        None => CwTable::try_parse(&text),
+    };
+
+    result.map_err(|e| Hoi4LoadError::UnparseableFile {
+        file: path.into(),
+        message: e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::sync::Arc;
+    use clausewitz_data::vfs::{FileSystem, DiskFileSystem, MemoryFileSystem, OverlayFileSystem};
+    use clausewitz_data::cache::ParseCache;
+    use clausewitz_data::CwTable;
+    use super::{CwGameHoi4, Hoi4LoadOptions, Hoi4LoadError, Hoi4Mod, Hoi4State, Hoi4Country, Hoi4Politics, Hoi4Date,
+        Hoi4IdAllocator, Hoi4Idea,
+        Hoi4ModFile, Hoi4FileEncoding, Hoi4ExportError, Hoi4EditError};
+
+    fn fixture() -> MemoryFileSystem {
+        let fs = MemoryFileSystem::new();
+        fs.write_text("common/country_tags/00_countries.txt", "FRA = \"countries/France.txt\"", false).unwrap();
+        fs.write_text("common/countries/France.txt", "color = { 57 57 200 }", false).unwrap();
+        fs.write_text("history/countries/FRA - France.txt", "capital = 16\noob = \"FRA_1936\"", true).unwrap();
+        fs.write_text("history/states/16-Ile de France.txt",
+            "state={ id=16 name=\"STATE_16\" history={ owner = FRA } }", false).unwrap();
+        fs.write_text("history/units/FRA_1936.txt", "units = { }", false).unwrap();
+        fs
+    }
+
+    #[test]
+    fn load_from_memory() {
+        let game = CwGameHoi4::load(Arc::new(fixture()), &Default::default()).unwrap();
+
+        let country = game.country_for_tag("FRA").unwrap();
+        assert_eq!(country.name(), "France");
+        assert_eq!(country.units(), Some(&"FRA_1936".into()));
+        assert_eq!(game.states()[0].owner(), Some(&"FRA".into()));
+        assert_eq!(game.states()[0].id(), Some(&"16".into()));
+        assert_eq!(Hoi4Country::load("GER".into(), "Germany".into(), CwTable::new(), CwTable::new()).units(), None);
+        assert_eq!(game.states()[0].history_source("owner").unwrap().to_string(),
+            "history/states/16-Ile de France.txt:1:41 (game)");
+        assert!(game.units_for_id("FRA_1936").is_some());
+    }
+
+    #[test]
+    fn export_to_memory() {
+        let game = CwGameHoi4::load(Arc::new(fixture()), &Default::default()).unwrap();
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.add_country(game.country_for_tag("FRA").unwrap().clone());
+        modif.add_state(game.states()[0].clone());
+
+        let fs = MemoryFileSystem::new();
+        modif.export_to(&fs).unwrap();
+
+        assert_eq!(fs.paths(), vec![
+            "test.mod",
+            "test/common/countries/France.txt",
+            "test/common/country_tags/countries.txt",
+            "test/descriptor.mod",
+            "test/history/countries/FRA - France.txt",
+            "test/history/states/16-Ile de France.txt",
+        ]);
+        assert!(fs.read_text("test.mod").unwrap().contains("path = \"mod/test\""));
+    }
+
+    #[test]
+    fn export_localisation() {
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.set_country_names("A01", "Burgundy", "Burgundian", "the Burgundian State");
+        modif.set_localisation("french", "A01", "Bourgogne");
+
+        let fs = MemoryFileSystem::new();
+        modif.export_to(&fs).unwrap();
+
+        let french = fs.read("test/localisation/french/test_l_french.yml").unwrap();
+        assert!(french.starts_with("\u{feff}".as_bytes()));
+        let french = fs.read_text("test/localisation/french/test_l_french.yml").unwrap();
+        assert!(french.starts_with("l_french:\n"));
+        assert!(french.contains(" A01:0 \"Bourgogne\"\n"));
+        assert!(french.contains(" A01_fascism_DEF:0 \"the Burgundian State\"\n"));
+        let german = fs.read_text("test/localisation/german/test_l_german.yml").unwrap();
+        assert!(german.contains(" A01:0 \"Burgundy\"\n"));
+    }
+
+    #[test]
+    fn export_ideas() {
+        let mut idea = Hoi4Idea::new("test_spirit", "country");
+        idea.removal_cost = Some(-1.0);
+        idea.modifier.set("stability_factor", "0.1".into());
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.add_idea_definition(idea, "Test Spirit", "A test.");
+
+        let out = MemoryFileSystem::new();
+        modif.export_to(&out).unwrap();
+        let english = out.read_text("test/localisation/english/test_l_english.yml").unwrap();
+        assert!(english.contains(" test_spirit:0 \"Test Spirit\"\n"));
+        assert!(english.contains(" test_spirit_desc:0 \"A test.\"\n"));
+
+        // The exported ideas should load as they were defined
+        let fs = fixture();
+        fs.write("common/ideas/test.txt", &out.read("test/common/ideas/test.txt").unwrap()).unwrap();
+        let game = CwGameHoi4::load(Arc::new(fs), &Default::default()).unwrap();
+        let idea = game.idea("test_spirit").unwrap();
+        assert_eq!(idea.category, "country");
+        assert_eq!(idea.removal_cost, Some(-1.0));
+        assert_eq!(idea.modifier.get("stability_factor").unwrap().as_string().unwrap(), "0.1");
+    }
+
+    #[test]
+    fn export_extra_files() {
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.add_file(Hoi4ModFile::table("events/test.txt", CwTable::parse("namespace = test"))).unwrap();
+        let mut text = Hoi4ModFile::text("map\\test.csv", "1;Zürich;€");
+        text.encoding = Hoi4FileEncoding::Windows1252;
+        modif.add_file(text).unwrap();
+        modif.add_file(Hoi4ModFile::binary("gfx/flags/A01.tga", vec![0, 1, 2])).unwrap();
+
+        assert_eq!(modif.add_file(Hoi4ModFile::text("Events/Test.txt", "")),
+            Err(Hoi4ExportError::FileConflict("Events/Test.txt".into())));
+        assert_eq!(modif.add_file(Hoi4ModFile::text("../test.txt", "")),
+            Err(Hoi4ExportError::InvalidPath("../test.txt".into())));
+
+        let fs = MemoryFileSystem::new();
+        modif.export_to(&fs).unwrap();
+        assert_eq!(fs.read_text("test/events/test.txt").unwrap().trim(), "namespace = test");
+        assert_eq!(fs.read("test/map/test.csv").unwrap(), b"1;Z\xfcrich;\x80".to_vec());
+        assert_eq!(fs.read("test/gfx/flags/A01.tga").unwrap(), vec![0, 1, 2]);
+
+        // Files can't replace the ones generated for the mod's content
+        modif.add_file(Hoi4ModFile::text("common/country_tags/countries.txt", "")).unwrap();
+        let fs = MemoryFileSystem::new();
+        assert_eq!(modif.export_to(&fs),
+            Err(Hoi4ExportError::FileConflict("common/country_tags/countries.txt".into())));
+        assert!(fs.paths().is_empty());
+    }
+
+    #[test]
+    fn export_replaces_only_exports() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-game-hoi4-export-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("test")).unwrap();
+
+        // Someone else's mod with the same name is left alone
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.set_backups(2);
+        assert_eq!(modif.export(&dir), Err(Hoi4ExportError::NotExported(dir.join("test").display().to_string())));
+        assert!(!dir.join("test.mod").exists());
+        fs::remove_dir_all(dir.join("test")).unwrap();
+
+        // So is someone else's .mod file or archive
+        fs::write(dir.join("test.mod"), "name = \"Other Mod\"").unwrap();
+        assert_eq!(modif.export(&dir), Err(Hoi4ExportError::NotExported(dir.join("test.mod").display().to_string())));
+        fs::write(dir.join("test.zip"), "not an archive").unwrap();
+        assert_eq!(modif.export_zip(&dir), Err(Hoi4ExportError::NotExported(dir.join("test.zip").display().to_string())));
+        fs::remove_file(dir.join("test.zip")).unwrap();
+        fs::remove_file(dir.join("test.mod")).unwrap();
+
+        // Exports from before the marker are recognised by the name in their descriptor
+        fs::create_dir_all(dir.join("test")).unwrap();
+        fs::write(dir.join("test/descriptor.mod"), "name = \"Test Mod\"").unwrap();
+        fs::write(dir.join("test.mod"), "name = \"Test Mod\"").unwrap();
+
+        for _ in 0..4 {
+            modif.export(&dir).unwrap();
+        }
+        assert!(dir.join("test/descriptor.mod").is_file());
+        assert!(dir.join("test.mod").is_file());
+        assert!(dir.join("test.backup1/descriptor.mod").is_file());
+        assert!(dir.join("test.backup2/descriptor.mod").is_file());
+        assert!(dir.join("test.backup2/.exported").is_file());
+        assert!(!dir.join("test.backup3").exists());
+        assert!(!dir.join("test.tmp").exists());
+        assert!(modif.diff(&dir).unwrap().is_empty());
+
+        // With backups nothing is moved to the .old folder, so someone else's is left alone
+        fs::create_dir_all(dir.join("test.old")).unwrap();
+        fs::write(dir.join("test.old/descriptor.mod"), "name = \"Other Mod\"").unwrap();
+        modif.export(&dir).unwrap();
+        assert!(dir.join("test.old/descriptor.mod").is_file());
+
+        // Broken descriptors from other mods are refused rather than crashing the export
+        fs::write(dir.join("test.backup2/descriptor.mod"), "name = {").unwrap();
+        fs::remove_file(dir.join("test.backup2/.exported")).unwrap();
+        assert_eq!(modif.export(&dir), Err(Hoi4ExportError::NotExported(dir.join("test.backup2").display().to_string())));
+        fs::write(dir.join("test.mod"), "name = {").unwrap();
+        assert_eq!(modif.export_zip(&dir), Err(Hoi4ExportError::NotExported(dir.join("test.mod").display().to_string())));
+        fs::remove_dir_all(dir.join("test.backup2")).unwrap();
+        fs::remove_file(dir.join("test.mod")).unwrap();
+
+        assert!(modif.diff_zip(&dir).unwrap().added.contains(&"descriptor.mod".to_string()));
+        modif.export_zip(&dir).unwrap();
+        assert!(modif.diff_zip(&dir).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn diff_export() {
+        let mut modif = Hoi4Mod::new("test", "Test Mod", "1.0.1");
+        modif.add_state(Hoi4State::load("16-Ile de France.txt".into(),
+            CwTable::parse("state = { id = 16 manpower = 100 history = { owner = FRA } }")));
+        modif.add_file(Hoi4ModFile::binary("gfx/flags/A01.tga", vec![0])).unwrap();
+
+        let existing = MemoryFileSystem::new();
+        existing.write_text("history/states/16-Ile de France.txt",
+            "state = { id = 16 manpower = 50 history = { owner = FRA add_core_of = FRA } }", false).unwrap();
+        existing.write("gfx/flags/A01.tga", &[1]).unwrap();
+        existing.write_text("events/old.txt", "namespace = old", false).unwrap();
+        existing.write_text(".exported", "test", false).unwrap();
+
+        let diff = modif.diff_files(&existing).unwrap();
+        assert!(diff.added.contains(&"descriptor.mod".to_string()));
+        assert_eq!(diff.removed, vec!["events/old.txt"]);
+        assert_eq!(diff.modified.len(), 2);
+        assert_eq!(diff.modified[0].0, "gfx/flags/A01.tga");
+        assert_eq!(diff.modified[0].1.len(), 0);
+        let changes: Vec<_> = diff.modified[1].1.iter().map(|c| c.to_string()).collect();
+        assert_eq!(changes, vec!["~ state/manpower: 50 -> 100", "- state/history/add_core_of = FRA"]);
+        assert_eq!(existing.paths().len(), 4);
+    }
+
+    #[test]
+    fn load_errors_and_warnings() {
+        let fs = fixture();
+        fs.write_text("common/country_tags/00_countries.txt",
+            "FRA = \"countries/France.txt\"\nGER = \"countries/Germany.txt\"", false).unwrap();
+        fs.write_text("history/states/1-Broken.txt", "state={ id=1 ", false).unwrap();
+        fs.write_text("history/states/2-Broken.txt", "state={ id=2 } }", false).unwrap();
+        let fs = Arc::new(fs);
+
+        assert!(CwGameHoi4::load(fs.clone(), &Default::default()).is_err());
+
+        let options = Hoi4LoadOptions { collect_warnings: true, ..Default::default() };
+        let game = CwGameHoi4::load(fs, &options).unwrap();
+        assert_eq!(game.countries().len(), 1);
+        assert_eq!(game.states().len(), 1);
+        assert_eq!(game.warnings().len(), 3);
+        assert_eq!(game.warnings()[0], Hoi4LoadError::UnmatchedTag {
+            tag: "GER".into(),
+            message: "Points to \"common/countries/Germany.txt\" which does not exist".into(),
+        });
+        if let Hoi4LoadError::UnparseableFile { ref file, .. } = game.warnings()[1] {
+            assert_eq!(file, "history/states/1-Broken.txt");
+        } else {
+            assert!(false, "Wrong warning type!");
+        }
+        if let Hoi4LoadError::UnparseableFile { ref file, .. } = game.warnings()[2] {
+            assert_eq!(file, "history/states/2-Broken.txt");
+        } else {
+            assert!(false, "Wrong warning type!");
+        }
+    }
+
+    #[test]
+    fn load_mismatched_history() {
+        let fs = fixture();
+        fs.write_text("common/country_tags/00_countries.txt",
+            "FRA = \"countries/France.txt\"\nGER = \"countries/Germany.txt\"", false).unwrap();
+        fs.write_text("common/countries/Germany.txt", "color = { 57 57 57 }", false).unwrap();
+        fs.write_text("history/countries/GER - German Reich.txt", "capital = 64", true).unwrap();
+        let fs = Arc::new(fs);
+
+        assert_eq!(CwGameHoi4::load(fs.clone(), &Default::default()).unwrap().countries().len(), 2);
+
+        let options = Hoi4LoadOptions { collect_warnings: true, ..Default::default() };
+        let game = CwGameHoi4::load(fs, &options).unwrap();
+        assert_eq!(game.country_for_tag("GER").unwrap().history_table().get("capital").unwrap().as_string().unwrap(), "64");
+        assert_eq!(game.warnings(), &vec![Hoi4LoadError::UnmatchedTag {
+            tag: "GER".into(),
+            message: "Expected \"history/countries/GER - Germany.txt\", \
+                using \"history/countries/GER - German Reich.txt\" instead".into(),
+        }]);
+    }
+
+    #[test]
+    fn load_cached_without_reading() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("clausewitz-game-hoi4-cache-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let disk = DiskFileSystem::new(dir.join("game"));
+        let memory = fixture();
+        for path in memory.paths() {
+            disk.write(&path, &memory.read(&path).unwrap()).unwrap();
+        }
+
+        let options = Hoi4LoadOptions { cache: Some(ParseCache::new(dir.join("cache")).unwrap()), ..Default::default() };
+        let game = CwGameHoi4::at(&dir.join("game"), &options).unwrap();
+        assert_eq!(game.states()[0].owner(), Some(&"FRA".into()));
+
+        // Swap in a different entry for the unchanged file, getting it back means the loader
+        // didn't read the file again
+        let path = "history/states/16-Ile de France.txt";
+        let (size, mtime) = (disk.size(path).unwrap(), disk.modified(path).unwrap());
+        options.cache.as_ref().unwrap().parse(&format!("game:{}", path),
+            "state={ id=16 name=\"STATE_16\" history={ owner = GER } }", size, mtime).unwrap();
+        let game = CwGameHoi4::at(&dir.join("game"), &options).unwrap();
+        assert_eq!(game.states()[0].owner(), Some(&"GER".into()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_missing_directory() {
+        let result = CwGameHoi4::load(Arc::new(MemoryFileSystem::new()), &Default::default());
+        assert_eq!(result.err(), Some(Hoi4LoadError::MissingDirectory("history/countries".into())));
+    }
+
+    #[test]
+    fn load_layered() {
+        let mod_fs = MemoryFileSystem::new();
+        mod_fs.write_text("common/country_tags/mod_countries.txt", "GER = \"countries/Germany.txt\"", false).unwrap();
+        mod_fs.write_text("common/countries/Germany.txt", "color = { 50 50 50 }", false).unwrap();
+        mod_fs.write_text("history/countries/GER - Germany.txt", "capital = 64", false).unwrap();
+        mod_fs.write_text("history/states/64-Brandenburg.txt",
+            "state={ id=64 name=\"STATE_64\" history={ owner = GER } }", false).unwrap();
+
+        let mut fs = OverlayFileSystem::new();
+        fs.push_layer("game", Box::new(fixture()));
+        fs.push_layer("Germany Mod", Box::new(mod_fs));
+        fs.replace_dir("history/states");
+        let game = CwGameHoi4::load(Arc::new(fs), &Default::default()).unwrap();
+
+        assert_eq!(game.countries().len(), 2);
+        assert_eq!(game.states().len(), 1);
+        assert_eq!(game.states()[0].history_source("owner").unwrap().layer, Some(Arc::new("Germany Mod".into())));
+    }
+
+    #[test]
+    fn load_tag_files() {
+        let fs = fixture();
+        fs.write_text("common/country_tags/00_countries.txt",
+            "FRA = \"countries/France.txt\"\nFRN = \"countries/France Libre.txt\"\ndynamic_tags = yes\nD01 = \"countries/D01.txt\"", false).unwrap();
+        fs.write_text("common/countries/France Libre.txt", "color = { 1 2 3 }", false).unwrap();
+        fs.write_text("common/countries/D01.txt", "color = { 1 2 3 }", false).unwrap();
+        fs.write_text("history/countries/FRN - France Libre.txt", "capital = 16", false).unwrap();
+        let game = CwGameHoi4::load(Arc::new(fs), &Default::default()).unwrap();
+
+        assert_eq!(game.countries().len(), 2);
+        assert_eq!(game.country_for_tag("FRA").unwrap().history_table().get("capital").unwrap().as_string(),
+            Some(&"16".into()));
+        assert_eq!(game.country_for_tag("FRN").unwrap().name(), "France Libre");
+        assert_eq!(game.dynamic_countries().len(), 1);
+        assert!(game.dynamic_countries()[0].is_dynamic());
+        assert!(game.has_tag("D01"));
+    }
+
+    #[test]
+    fn typed_state() {
+        let data = CwTable::parse("state = { id = 1 name = \"STATE_1\" manpower = 1200 state_category = city \
+            provinces = { 11 12 } resources = { steel = 12 oil = 4.5 } \
+            history = { owner = FRA victory_points = { 11 10 } Victory_Points = { 12 1 } \
+                buildings = { infrastructure = 6 12 = { naval_base = 3 } } } }");
+        let mut state = Hoi4State::load("1-Test.txt".into(), data);
+
+        assert_eq!(state.provinces(), Some(vec![11, 12]));
+        assert_eq!(state.manpower(), Some(1200));
+        assert_eq!(state.state_category(), Some(&"city".into()));
+        assert_eq!(state.resources(), vec![("steel".into(), 12.0), ("oil".into(), 4.5)]);
+        assert_eq!(state.buildings(), vec![("infrastructure".into(), 6)]);
+        assert_eq!(state.province_buildings(12), vec![("naval_base".into(), 3)]);
+        assert_eq!(state.victory_points(), vec![(11, 10.0), (12, 1.0)]);
+        assert!(!state.is_impassable());
+
+        state.set_victory_points(12, 5.0).unwrap();
+        state.set_province_building(11, "bunker", 2).unwrap();
+        state.set_resource("chromium", 8.0).unwrap();
+        state.add_claim("GER".into()).unwrap();
+        state.set_impassable(true).unwrap();
+        assert_eq!(state.victory_points(), vec![(11, 10.0), (12, 5.0)]);
+        assert_eq!(state.province_buildings(11), vec![("bunker".into(), 2)]);
+        assert_eq!(state.resource("chromium"), Some(8.0));
+        assert_eq!(state.claims(), vec!["GER"]);
+        assert!(state.is_impassable());
+
+        let mut broken = Hoi4State::load("2-Broken.txt".into(), CwTable::new());
+        assert_eq!(broken.provinces(), None);
+        assert_eq!(broken.set_manpower(10), Err(Hoi4EditError { file: "2-Broken.txt".into(), key: "state".into() }));
+        assert_eq!(broken.set_owner("GER".into()), Err(Hoi4EditError { file: "2-Broken.txt".into(), key: "state".into() }));
+        assert_eq!(broken.add_core("GER".into()), Err(Hoi4EditError { file: "2-Broken.txt".into(), key: "state".into() }));
+        assert_eq!(broken.owner(), None);
+    }
+
+    #[test]
+    fn typed_country_history() {
+        let history = CwTable::parse("capital = 16 set_research_slots = 3 Add_Ideas = French_Army \
+            SET_TECHNOLOGY = { infantry_weapons = 1 Tech_Support = 1 } \
+            set_politics = { ruling_party = democratic last_election = \"1932.5.1\" some_flag = yes } \
+            set_popularities = { democratic = 60 fascism = 40 } \
+            create_country_leader = { name = \"Albert Lebrun\" ideology = conservatism traits = { } custom = foo }");
+        let mut country = Hoi4Country::load("FRA".into(), "France".into(), CwTable::new(), history);
+
+        assert_eq!(country.research_slots(), Some(3));
+        assert_eq!(country.technologies(), vec!["infantry_weapons", "Tech_Support"]);
+        assert_eq!(country.politics().unwrap().last_election, Some(Hoi4Date::new(1932, 5, 1)));
+        assert_eq!(country.popularities(), vec![("democratic".into(), 60.0), ("fascism".into(), 40.0)]);
+        let leader = country.country_leaders().pop().unwrap();
+        assert_eq!(leader.name, "Albert Lebrun");
+        assert_eq!(leader.to_table().get("custom").unwrap().as_string().unwrap(), "foo");
+
+        country.add_idea("maginot_line".into());
+        country.add_technology("tech_support");
+        country.remove_idea("french_army");
+        country.remove_technology("tech_support");
+        country.set_politics(&Hoi4Politics::new("fascism"));
+        assert_eq!(country.ideas(), vec!["maginot_line"]);
+        assert_eq!(country.technologies(), vec!["infantry_weapons"]);
+        let politics = country.history_table().get("set_politics").unwrap().as_table().unwrap();
+        assert_eq!(politics.get("ruling_party").unwrap().as_string().unwrap(), "fascism");
+        assert!(politics.has_key("some_flag"));
+    }
+
+    #[test]
+    fn typed_units() {
+        let fs = fixture();
+        fs.write_text("history/countries/FRA - France.txt", "capital = 16 oob = \"FRA_1936\" \
+            if = { limit = { has_dlc = \"Man the Guns\" } set_naval_oob = \"FRA_1936_naval_mtg\" }", true).unwrap();
+        fs.write_text("history/units/FRA_1936.txt", "division_template = { name = \"Infanterie\" \
+                regiments = { infantry = { x = 0 y = 0 } infantry = { x = 0 y = 1 } } support = { engineer = { x = 0 y = 0 } } } \
+            units = { division = { location = 11 division_template = \"Infanterie\" start_experience_factor = 0.3 } \
+                division = { location = 12 division_template = \"Infanterie\" } } \
+            air_wings = { 16 = { fighter_equipment_0 = { owner = \"FRA\" amount = 80 } } 17 = { cas_equipment_1 = { owner = \"FRA\" amount = 20 } } } \
+            instant_effect = { add_equipment_production = { equipment = { type = infantry_equipment_1 creator = \"FRA\" } requested_factories = 4 } }", false).unwrap();
+        fs.write_text("history/units/FRA_1936_naval_mtg.txt", "units = { fleet = { name = \"Marine\" naval_base = 11 \
+            task_force = { name = \"Force\" location = 11 ship = { name = \"Dunkerque\" definition = battle_cruiser } } } }", false).unwrap();
+        let game = CwGameHoi4::load(Arc::new(fs), &Default::default()).unwrap();
+
+        let country = game.country_for_tag("FRA").unwrap();
+        assert_eq!(country.oob_ids(), vec!["FRA_1936", "FRA_1936_naval_mtg"]);
+        assert_eq!(game.units_for_country(country).len(), 2);
+
+        let mut units = game.units_for_id("FRA_1936").unwrap().clone();
+        let templates = units.division_templates();
+        assert_eq!(templates[0].regiments.len(), 2);
+        assert_eq!(templates[0].support[0].unit, "engineer");
+        assert_eq!(units.divisions()[0].experience_factor, Some(0.3));
+        assert_eq!(units.air_wings().len(), 2);
+        assert_eq!(units.production_lines()[0].requested_factories, 4);
+
+        units.retain_located_in(&[11], &[16]);
+        assert_eq!(units.divisions().len(), 1);
+        assert_eq!(units.air_wings()[0].equipment, "fighter_equipment_0");
+        assert_eq!(units.division_templates().len(), 1);
+
+        let naval = game.units_for_id("FRA_1936_naval_mtg").unwrap();
+        assert_eq!(naval.fleets()[0].task_forces[0].ships[0].definition, "battle_cruiser");
+    }
+
+    #[test]
+    fn load_characters() {
+        let fs = fixture();
+        fs.write_text("history/countries/FRA - France.txt", "capital = 16 oob = \"FRA_1936\" \
+            recruit_character = FRA_albert_lebrun recruit_character = FRA_maurice_gamelin", true).unwrap();
+        fs.write_text("common/characters/FRA.txt", "characters = { \
+            FRA_albert_lebrun = { name = \"Albert Lebrun\" portraits = { civilian = { large = \"gfx/leaders/FRA/lebrun.dds\" } } \
+                country_leader = { ideology = conservatism traits = { } } } \
+            FRA_maurice_gamelin = { name = \"Maurice Gamelin\" field_marshal = { skill = 3 id = 50 } \
+                corps_commander = { skill = 2 legacy_id = 51 } \
+                advisor = { slot = army_chief idea_token = FRA_maurice_gamelin_army_chief cost = 100 } } }", true).unwrap();
+        let game = CwGameHoi4::load(Arc::new(fs), &Default::default()).unwrap();
+
+        let mut country = game.country_for_tag("FRA").unwrap().clone();
+        let characters = game.characters_for_country(&country);
+        assert_eq!(characters.len(), 2);
+        assert!(characters[0].is_country_leader());
+        assert_eq!(characters[0].portraits[0].large, Some("gfx/leaders/FRA/lebrun.dds".into()));
+        assert_eq!(characters[1].roles_of("advisor")[0].slot(), Some(&"army_chief".into()));
+
+        let mut ids = Hoi4IdAllocator::new(1000);
+        let copy = characters[1].clone_as("A01_maurice_gamelin", &mut ids.remap());
+        country.replace_character(&characters[1].id, &copy.id);
+        assert_eq!(country.character_ids(), vec!["FRA_albert_lebrun", "A01_maurice_gamelin"]);
+        assert_eq!(copy.roles[0].data.get("id").unwrap().as_string().unwrap(), "1000");
+        assert_eq!(copy.roles[1].data.get("legacy_id").unwrap().as_string().unwrap(), "1001");
+        assert_eq!(copy.roles[2].data.get("idea_token").unwrap().as_string().unwrap(), "A01_maurice_gamelin");
+        assert_eq!(copy.to_table().get("name").unwrap().as_string().unwrap(), "Maurice Gamelin");
+
+        // Every copy needs its own legacy ID
+        let other = characters[1].clone_as("A02_maurice_gamelin", &mut ids.remap());
+        assert_eq!(other.roles[1].data.get("legacy_id").unwrap().as_string().unwrap(), "1003");
+    }
+
+    #[test]
+    fn load_localisation() {
+        let fs = fixture();
+        fs.write_text("history/states/16-Ile de France.txt",
+            "state={ id=16 name=\"STATE_16\" history={ owner = FRA victory_points = { 11506 30 } } }", false).unwrap();
+        fs.write_text("history/countries/FRA - France.txt", "oob = \"FRA_1936\" set_politics = { ruling_party = fascism }", true).unwrap();
+        fs.write_text("localisation/english/countries_l_english.yml",
+            "l_english:\n FRA:0 \"France\"\n FRA_fascism:0 \"French State\"\n FRA_ADJ:0 \"French\"\n", true).unwrap();
+        fs.write_text("localisation/english/state_names_l_english.yml",
+            "l_english:\n STATE_16:0 \"Île-de-France\"\n VICTORY_POINTS_11506:0 \"Paris\"\n", true).unwrap();
+        fs.write_text("localisation/english/replace/states_l_english.yml",
+            "l_english:\n STATE_16:0 \"Paris Region\"\n", true).unwrap();
+        fs.write_text("localisation/broken_l_english.yml", "FRA: France", true).unwrap();
+        let options = Hoi4LoadOptions { collect_warnings: true, ..Default::default() };
+        let game = CwGameHoi4::load(Arc::new(fs), &options).unwrap();
+        let localisation = game.localisation();
+
+        let country = game.country_for_tag("FRA").unwrap();
+        assert_eq!(country.display_name(localisation, "english"), Some(&"French State".into()));
+        assert_eq!(country.adjective(localisation, "english"), Some(&"French".into()));
+        assert_eq!(country.definite_name(localisation, "english"), None);
+        assert_eq!(game.states()[0].display_name(localisation, "english"), Some(&"Paris Region".into()));
+        assert_eq!(game.states()[0].victory_point_names(localisation, "english"), vec![(11506, &"Paris".to_string())]);
+        assert_eq!(game.province_name(11506, "english"), Some(&"Paris".into()));
+        assert_eq!(game.warnings().len(), 1);
+    }
 }
@@ -0,0 +1,515 @@
+//! The province map, which provinces exist and which ones border each other.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use clausewitz_data::CwTable;
+use clausewitz_data::bitmap::Bitmap;
+use clausewitz_data::vfs::{self, FileSystem};
+use error::Hoi4LoadError;
+use {Hoi4State, load_file};
+
+/// A province as defined in `map/definition.csv`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4Province {
+    pub id: u32,
+    /// The color the province has in `map/provinces.bmp`.
+    pub color: [u8; 3],
+    /// "land", "sea" or "lake".
+    pub kind: String,
+    pub coastal: bool,
+    pub terrain: String,
+    pub continent: u32,
+}
+
+/// A special connection between provinces from `map/adjacencies.csv`, like a strait.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4Adjacency {
+    pub from: u32,
+    pub to: u32,
+    /// "sea" for straits, "impassable" for borders that can't be crossed, or empty for other links.
+    pub kind: String,
+    /// The sea province a strait crosses.
+    pub through: Option<u32>,
+}
+
+/// A group of provinces sharing weather and air and naval regions, from `map/strategicregions`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4StrategicRegion {
+    pub id: u32,
+    pub name: String,
+    pub provinces: Vec<u32>,
+}
+
+/// A group of states sharing supply in older versions of the game, from `map/supplyareas`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4SupplyArea {
+    pub id: u32,
+    pub name: String,
+    pub value: u32,
+    pub states: Vec<u32>,
+}
+
+pub struct Hoi4Map {
+    provinces: Vec<Hoi4Province>,
+    /// The position of every province in `provinces` by its ID.
+    province_index: HashMap<u32, usize>,
+    adjacencies: Vec<Hoi4Adjacency>,
+    neighbours: BTreeMap<u32, BTreeSet<u32>>,
+    continents: Vec<String>,
+    strategic_regions: Vec<Hoi4StrategicRegion>,
+    supply_areas: Vec<Hoi4SupplyArea>,
+    supply_nodes: Vec<u32>,
+}
+
+impl Hoi4Map {
+    /// Loads the definitions, map image, adjacencies and everything grouping provinces together
+    /// from the game's `map` directory. The names of the main map files are read from
+    /// `map/default.map` if it's there.
+    pub fn load(fs: &dyn FileSystem) -> Result<Self, Hoi4LoadError> {
+        let default_map = if fs.is_file("map/default.map") {
+            try!(load_file(fs, "map/default.map", None))
+        } else {
+            CwTable::new()
+        };
+        let map_file = |key: &str, default: &str| vfs::join("map",
+            default_map.get(key).and_then(|v| v.as_string()).map(|v| v.as_str()).unwrap_or(default));
+
+        let definitions_file = map_file("definitions", "definition.csv");
+        let definitions = try!(read_text(fs, &definitions_file));
+        let provinces = try!(parse_definitions(&definitions)
+            .map_err(|e| Hoi4LoadError::UnparseableFile { file: definitions_file.clone(), message: e }));
+
+        let image_file = map_file("provinces", "provinces.bmp");
+        let image = try!(fs.read(&image_file).map_err(|e| Hoi4LoadError::UnreadableFile {
+            file: image_file.clone(), message: e.to_string()
+        }));
+        let bitmap = try!(Bitmap::decode(&image)
+            .map_err(|e| Hoi4LoadError::UnparseableFile { file: image_file.clone(), message: e }));
+
+        // Not every mod has adjacencies, it's fine to go without
+        let adjacencies_file = map_file("adjacencies", "adjacencies.csv");
+        let adjacencies = if fs.is_file(&adjacencies_file) {
+            let text = try!(read_text(fs, &adjacencies_file));
+            try!(parse_adjacencies(&text)
+                .map_err(|e| Hoi4LoadError::UnparseableFile { file: adjacencies_file.clone(), message: e }))
+        } else {
+            Vec::new()
+        };
+
+        let mut map = Self::from_parts(provinces, &bitmap, adjacencies);
+
+        // Continents are referred to by their position in this list, starting at 1
+        let continent_file = map_file("continent", "continent.txt");
+        if fs.is_file(&continent_file) {
+            let continents = try!(load_file(fs, &continent_file, None));
+            map.continents = continents.get("continents")
+                .and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(|v| v.as_string().cloned()).collect())
+                .unwrap_or_default();
+        }
+
+        for region in try!(load_all(fs, "map/strategicregions", "strategic_region")) {
+            map.strategic_regions.push(Hoi4StrategicRegion {
+                id: number(&region, "id").unwrap_or(0),
+                name: region.get("name").and_then(|v| v.as_string()).cloned().unwrap_or_default(),
+                provinces: numbers(&region, "provinces"),
+            });
+        }
+
+        // Supply areas were replaced by supply nodes in later versions
+        for area in try!(load_all(fs, "map/supplyareas", "supply_area")) {
+            map.supply_areas.push(Hoi4SupplyArea {
+                id: number(&area, "id").unwrap_or(0),
+                name: area.get("name").and_then(|v| v.as_string()).cloned().unwrap_or_default(),
+                value: number(&area, "value").unwrap_or(0),
+                states: numbers(&area, "states"),
+            });
+        }
+        if fs.is_file("map/supply_nodes.txt") {
+            let text = try!(read_text(fs, "map/supply_nodes.txt"));
+            map.supply_nodes = try!(parse_supply_nodes(&text)
+                .map_err(|e| Hoi4LoadError::UnparseableFile { file: "map/supply_nodes.txt".into(), message: e }));
+        }
+
+        Ok(map)
+    }
+
+    pub fn from_parts(provinces: Vec<Hoi4Province>, bitmap: &Bitmap, adjacencies: Vec<Hoi4Adjacency>) -> Self {
+        let mut map = Hoi4Map {
+            province_index: provinces.iter().enumerate().map(|(i, p)| (p.id, i)).collect(),
+            provinces: provinces,
+            adjacencies: Vec::new(),
+            neighbours: BTreeMap::new(),
+            continents: Vec::new(),
+            strategic_regions: Vec::new(),
+            supply_areas: Vec::new(),
+            supply_nodes: Vec::new(),
+        };
+
+        for (a, b) in bitmap_borders(&map.provinces, bitmap) {
+            map.connect(a, b);
+        }
+
+        for adjacency in &adjacencies {
+            if adjacency.kind == "impassable" {
+                map.disconnect(adjacency.from, adjacency.to);
+            } else {
+                map.connect(adjacency.from, adjacency.to);
+            }
+        }
+        map.adjacencies = adjacencies;
+
+        map
+    }
+
+    pub fn provinces(&self) -> &Vec<Hoi4Province> {
+        &self.provinces
+    }
+
+    pub fn province(&self, id: u32) -> Option<&Hoi4Province> {
+        self.province_index.get(&id).map(|&i| &self.provinces[i])
+    }
+
+    pub fn adjacencies(&self) -> &Vec<Hoi4Adjacency> {
+        &self.adjacencies
+    }
+
+    /// Gets the provinces that border a province or are connected to it by a strait.
+    pub fn neighbours(&self, province: u32) -> Vec<u32> {
+        self.neighbours.get(&province).map(|n| n.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn are_adjacent(&self, a: u32, b: u32) -> bool {
+        self.neighbours.get(&a).map(|n| n.contains(&b)).unwrap_or(false)
+    }
+
+    pub fn continents(&self) -> &Vec<String> {
+        &self.continents
+    }
+
+    pub fn strategic_regions(&self) -> &Vec<Hoi4StrategicRegion> {
+        &self.strategic_regions
+    }
+
+    pub fn supply_areas(&self) -> &Vec<Hoi4SupplyArea> {
+        &self.supply_areas
+    }
+
+    /// Gets the provinces with supply nodes.
+    pub fn supply_nodes(&self) -> &Vec<u32> {
+        &self.supply_nodes
+    }
+
+    pub fn continent_of_province(&self, province: u32) -> Option<&String> {
+        self.province(province)
+            .and_then(|p| if p.continent == 0 { None } else { self.continents.get(p.continent as usize - 1) })
+    }
+
+    /// Gets the continent most of a state's provinces are on.
+    pub fn continent_of_state(&self, state: &Hoi4State) -> Option<&String> {
+        most_common(state.provinces().unwrap_or_default().into_iter()
+            .filter_map(|p| self.continent_of_province(p)))
+    }
+
+    pub fn strategic_region_of_province(&self, province: u32) -> Option<&Hoi4StrategicRegion> {
+        self.strategic_regions.iter().find(|r| r.provinces.contains(&province))
+    }
+
+    /// Gets the strategic region most of a state's provinces are in.
+    pub fn strategic_region_of_state(&self, state: &Hoi4State) -> Option<&Hoi4StrategicRegion> {
+        let id = most_common(state.provinces().unwrap_or_default().into_iter()
+            .filter_map(|p| self.strategic_region_of_province(p))
+            .map(|r| r.id));
+        id.and_then(|id| self.strategic_regions.iter().find(|r| r.id == id))
+    }
+
+    pub fn supply_area_of_state(&self, state: &Hoi4State) -> Option<&Hoi4SupplyArea> {
+        let id = state.id().and_then(|id| id.parse().ok());
+        self.supply_areas.iter().find(|a| id.map(|id| a.states.contains(&id)).unwrap_or(false))
+    }
+
+    /// Gets the provinces in a state that have supply nodes.
+    pub fn supply_nodes_in_state(&self, state: &Hoi4State) -> Vec<u32> {
+        let provinces = state.provinces().unwrap_or_default();
+        self.supply_nodes.iter().cloned().filter(|n| provinces.contains(n)).collect()
+    }
+
+    /// Gets which states border each other, by state ID. States without any neighbours, like
+    /// islands without straits, are included with no neighbours.
+    pub fn state_neighbours(&self, states: &[Hoi4State]) -> BTreeMap<u32, BTreeSet<u32>> {
+        let mut province_states = HashMap::new();
+        let mut graph = BTreeMap::new();
+        for state in states {
+            let id = match state.id().and_then(|id| id.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            graph.insert(id, BTreeSet::new());
+            for province in state.provinces().unwrap_or_default() {
+                province_states.insert(province, id);
+            }
+        }
+
+        for (province, neighbours) in &self.neighbours {
+            let state = match province_states.get(province) {
+                Some(&state) => state,
+                None => continue,
+            };
+
+            for neighbour in neighbours {
+                match province_states.get(neighbour) {
+                    Some(&other) if other != state => {
+                        graph.get_mut(&state).unwrap().insert(other);
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        graph
+    }
+
+    fn connect(&mut self, a: u32, b: u32) {
+        if a == b {
+            return;
+        }
+        self.neighbours.entry(a).or_insert_with(BTreeSet::new).insert(b);
+        self.neighbours.entry(b).or_insert_with(BTreeSet::new).insert(a);
+    }
+
+    fn disconnect(&mut self, a: u32, b: u32) {
+        if let Some(n) = self.neighbours.get_mut(&a) {
+            n.remove(&b);
+        }
+        if let Some(n) = self.neighbours.get_mut(&b) {
+            n.remove(&a);
+        }
+    }
+}
+
+/// Finds all pairs of provinces that have pixels next to each other. The map wraps around
+/// horizontally, so the left and right edges border each other as well.
+fn bitmap_borders(provinces: &[Hoi4Province], bitmap: &Bitmap) -> BTreeSet<(u32, u32)> {
+    let by_color: HashMap<[u8; 3], u32> = provinces.iter().map(|p| (p.color, p.id)).collect();
+    let mut borders = BTreeSet::new();
+
+    let (width, height) = (bitmap.width(), bitmap.height());
+    for y in 0..height {
+        for x in 0..width {
+            let color = bitmap.pixel(x, y);
+            let right = bitmap.pixel((x + 1) % width, y);
+            let down = if y + 1 < height { Some(bitmap.pixel(x, y + 1)) } else { None };
+
+            for other in Some(right).into_iter().chain(down) {
+                if other == color {
+                    continue;
+                }
+
+                if let (Some(&a), Some(&b)) = (by_color.get(&color), by_color.get(&other)) {
+                    borders.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+
+    borders
+}
+
+/// Parses `map/definition.csv`, lines look like "1;128;34;64;land;false;forest;1".
+pub fn parse_definitions(text: &str) -> Result<Vec<Hoi4Province>, String> {
+    let mut provinces = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<_> = line.trim().split(';').collect();
+        if fields.len() < 8 || fields[0].len() == 0 {
+            continue;
+        }
+
+        let number = |field: &str| field.trim().parse::<u32>().map_err(|_| format!("Line {}: \"{}\" is not a number", i + 1, field));
+        let color = |field: &str| field.trim().parse::<u8>().map_err(|_| format!("Line {}: \"{}\" is not a color from 0 to 255", i + 1, field));
+        let id = try!(number(fields[0]));
+
+        // Province 0 is a placeholder for unused colors
+        if id == 0 {
+            continue;
+        }
+
+        provinces.push(Hoi4Province {
+            id: id,
+            color: [try!(color(fields[1])), try!(color(fields[2])), try!(color(fields[3]))],
+            kind: fields[4].into(),
+            coastal: fields[5] == "true",
+            terrain: fields[6].into(),
+            continent: try!(number(fields[7])),
+        });
+    }
+
+    Ok(provinces)
+}
+
+/// Parses `map/adjacencies.csv`, which starts with a header and ends with a line of -1s.
+pub fn parse_adjacencies(text: &str) -> Result<Vec<Hoi4Adjacency>, String> {
+    let mut adjacencies = Vec::new();
+
+    for (i, line) in text.lines().enumerate().skip(1) {
+        let fields: Vec<_> = line.trim().split(';').collect();
+        if fields.len() < 4 || fields[0].len() == 0 {
+            continue;
+        }
+        if fields[0] == "-1" {
+            break;
+        }
+
+        let number = |field: &str| field.trim().parse::<i64>().map_err(|_| format!("Line {}: \"{}\" is not a number", i + 1, field));
+        let through = try!(number(fields[3]));
+        adjacencies.push(Hoi4Adjacency {
+            from: try!(number(fields[0])) as u32,
+            to: try!(number(fields[1])) as u32,
+            kind: fields[2].into(),
+            through: if through < 0 { None } else { Some(through as u32) },
+        });
+    }
+
+    Ok(adjacencies)
+}
+
+/// Parses `map/supply_nodes.txt`, every line is the level of a node followed by its province.
+pub fn parse_supply_nodes(text: &str) -> Result<Vec<u32>, String> {
+    let mut nodes = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields.len() {
+            0 => continue,
+            2 => nodes.push(try!(fields[1].parse().map_err(|_| format!("Line {}: \"{}\" is not a number", i + 1, fields[1])))),
+            _ => return Err(format!("Line {}: expected a level and a province", i + 1)),
+        }
+    }
+    Ok(nodes)
+}
+
+/// Loads the tables with a key in all files in a directory, the directory is optional.
+fn load_all(fs: &dyn FileSystem, dir: &str, key: &str) -> Result<Vec<CwTable>, Hoi4LoadError> {
+    if !fs.is_dir(dir) {
+        return Ok(Vec::new());
+    }
+
+    let mut tables = Vec::new();
+    let file_names = try!(fs.list(dir).map_err(|_| Hoi4LoadError::MissingDirectory(dir.into())));
+    for file_name in file_names.iter().filter(|f| f.ends_with(".txt")) {
+        let file = try!(load_file(fs, &vfs::join(dir, &file_name), None));
+        tables.extend(file.get_all(key).into_iter().filter_map(|v| v.as_table()).cloned());
+    }
+    Ok(tables)
+}
+
+fn number(table: &CwTable, key: &str) -> Option<u32> {
+    table.get(key).and_then(|v| v.as_string()).and_then(|v| v.parse().ok())
+}
+
+fn numbers(table: &CwTable, key: &str) -> Vec<u32> {
+    table.get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|v| v.as_string()).filter_map(|v| v.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Gets the value that's in an iterator most often, the first one if there's a tie.
+fn most_common<T: PartialEq, I: Iterator<Item=T>>(values: I) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter().position(|c| c.0 == value) {
+            Some(i) => counts[i].1 += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+
+    let max = counts.iter().map(|c| c.1).max().unwrap_or(0);
+    counts.into_iter().find(|c| c.1 == max).map(|c| c.0)
+}
+
+/// Reads a file that may not be UTF-8, the map files are usually in Windows-1252.
+fn read_text(fs: &dyn FileSystem, path: &str) -> Result<String, Hoi4LoadError> {
+    fs.read(path)
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .map_err(|e| Hoi4LoadError::UnreadableFile { file: path.into(), message: e.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use clausewitz_data::CwTable;
+    use clausewitz_data::bitmap::Bitmap;
+    use clausewitz_data::vfs::{FileSystem, MemoryFileSystem};
+    use Hoi4State;
+    use super::{Hoi4Map, parse_definitions, parse_adjacencies};
+
+    #[test]
+    fn province_adjacency() {
+        let provinces = parse_definitions("0;0;0;0;land;false;unknown;0\n\
+            1;255;0;0;land;false;plains;1\n\
+            2;0;255;0;land;true;forest;1\n\
+            3;0;0;255;sea;false;ocean;0\n\
+            4;255;255;0;land;true;hills;2\n").unwrap();
+        let adjacencies = parse_adjacencies("From;To;Type;Through;start_x;start_y;stop_x;stop_y;adjacency_rule_name;Comment\n\
+            1;2;impassable;-1;-1;-1;-1;-1;;\n\
+            2;4;sea;3;-1;-1;-1;-1;;Strait\n\
+            -1;-1;;-1;-1;-1;-1;-1;-1\n").unwrap();
+        assert!(parse_definitions("1;256;0;0;land;false;plains;1\n").is_err());
+
+        // 1 2 2 3
+        // 1 1 3 3
+        let mut bitmap = Bitmap::new(4, 2);
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 255, 0], [0, 0, 255], [255, 0, 0], [255, 0, 0], [0, 0, 255], [0, 0, 255]];
+        for (i, color) in colors.iter().enumerate() {
+            bitmap.set_pixel(i as u32 % 4, i as u32 / 4, *color);
+        }
+
+        let map = Hoi4Map::from_parts(provinces, &bitmap, adjacencies);
+        assert_eq!(map.province(2).unwrap().terrain, "forest");
+        assert!(map.province(5).is_none());
+        assert_eq!(map.neighbours(1), vec![3]);
+        assert_eq!(map.neighbours(2), vec![3, 4]);
+        assert!(map.are_adjacent(3, 1));
+        assert!(!map.are_adjacent(1, 2));
+        assert_eq!(map.adjacencies()[1].through, Some(3));
+
+        let state = |id: u32, provinces: &str| Hoi4State::load(format!("{}-Test.txt", id),
+            CwTable::parse(&format!("state = {{ id = {} provinces = {{ {} }} }}", id, provinces)));
+        let graph = map.state_neighbours(&[state(1, "1"), state(2, "2"), state(3, "3 4")]);
+        assert_eq!(graph[&1].iter().cloned().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(graph[&2].iter().cloned().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(graph[&3].len(), 2);
+    }
+
+    #[test]
+    fn regions_and_continents() {
+        let fs = MemoryFileSystem::new();
+        fs.write_text("map/default.map", "definitions = \"definition.csv\"\nprovinces = \"provinces.bmp\"\ncontinent = \"continent.txt\"", false).unwrap();
+        fs.write_text("map/definition.csv", "0;0;0;0;land;false;unknown;0\n\
+            1;255;0;0;land;false;plains;1\n\
+            2;0;255;0;land;true;forest;2\n\
+            3;0;0;255;land;false;hills;2\n", false).unwrap();
+        let mut bitmap = Bitmap::new(3, 1);
+        bitmap.set_pixel(0, 0, [255, 0, 0]);
+        bitmap.set_pixel(1, 0, [0, 255, 0]);
+        bitmap.set_pixel(2, 0, [0, 0, 255]);
+        fs.write("map/provinces.bmp", &bitmap.encode()).unwrap();
+        fs.write_text("map/continent.txt", "continents = { europe asia }", false).unwrap();
+        fs.write_text("map/strategicregions/1-West.txt", "strategic_region = { id = 1 name = \"WEST\" provinces = { 1 2 } }", false).unwrap();
+        fs.write_text("map/strategicregions/2-East.txt", "strategic_region = { id = 2 name = \"EAST\" provinces = { 3 } }", false).unwrap();
+        fs.write_text("map/supplyareas/7-Area.txt", "supply_area = { id = 7 name = \"AREA\" value = 5 states = { 1 } }", false).unwrap();
+        fs.write_text("map/supply_nodes.txt", "1 2\n1 3\n", false).unwrap();
+
+        let map = Hoi4Map::load(&fs).unwrap();
+        assert_eq!(map.continent_of_province(1), Some(&"europe".into()));
+        assert_eq!(map.continent_of_province(0), None);
+        assert_eq!(map.strategic_region_of_province(3).unwrap().name, "EAST");
+        assert_eq!(map.supply_nodes(), &vec![2, 3]);
+
+        let state = Hoi4State::load("1-Test.txt".into(),
+            CwTable::parse("state = { id = 1 provinces = { 1 2 3 } }"));
+        assert_eq!(map.continent_of_state(&state), Some(&"asia".into()));
+        assert_eq!(map.strategic_region_of_state(&state).unwrap().id, 1);
+        assert_eq!(map.supply_area_of_state(&state).unwrap().value, 5);
+        assert_eq!(map.supply_nodes_in_state(&state), vec![2, 3]);
+    }
+}
@@ -1,35 +1,69 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::fs;
-use clausewitz_data::{file, CwTable, CwValue};
-use ::{Hoi4Country, Hoi4State, Hoi4Units};
+use clausewitz_data::CwTable;
+use clausewitz_data::archive::{self, ZipFileSystem};
+use clausewitz_data::diff::{self, CwChange};
+use clausewitz_data::localisation::{self, CwLocalisation};
+use clausewitz_data::vfs::{self, FileSystem, DiskFileSystem, MemoryFileSystem};
+use error::Hoi4ExportError;
+use idea;
+use ::{Hoi4Character, Hoi4Country, Hoi4Idea, Hoi4State, Hoi4Units, ModDescriptor, IDEOLOGIES, LANGUAGES};
 
 pub struct Hoi4Mod {
     name: String,
-    friendly_name: String,
-    game_version: String,
-    tags: Vec<String>,
+    descriptor: ModDescriptor,
 
     countries: Vec<Hoi4Country>,
     states: Vec<Hoi4State>,
     units: Vec<Hoi4Units>,
+    characters: Vec<Hoi4Character>,
+    ideas: Vec<Hoi4Idea>,
+    localisation: CwLocalisation,
+    files: Vec<Hoi4ModFile>,
+    backups: u32,
 }
 
 impl Hoi4Mod {
     pub fn new(name: &str, friendly_name: &str, game_version: &str) -> Self {
+        let mut descriptor = ModDescriptor::new(friendly_name);
+        descriptor.supported_version = Some(game_version.into());
+
         Hoi4Mod {
             name: name.into(),
-            friendly_name: friendly_name.into(),
-            game_version: game_version.into(),
-            tags: Vec::new(),
+            descriptor: descriptor,
 
             countries: Vec::new(),
             states: Vec::new(),
             units: Vec::new(),
+            characters: Vec::new(),
+            ideas: Vec::new(),
+            localisation: CwLocalisation::new(),
+            files: Vec::new(),
+            backups: 0,
         }
     }
 
+    pub fn descriptor(&self) -> &ModDescriptor {
+        &self.descriptor
+    }
+
+    pub fn descriptor_mut(&mut self) -> &mut ModDescriptor {
+        &mut self.descriptor
+    }
+
     pub fn add_tag(&mut self, tag: &str) {
-        self.tags.push(tag.into());
+        self.descriptor.tags.push(tag.into());
+    }
+
+    pub fn add_dependency(&mut self, mod_name: &str) {
+        self.descriptor.dependencies.push(mod_name.into());
+    }
+
+    pub fn add_replace_path(&mut self, path: &str) {
+        self.descriptor.replace_path.push(path.into());
     }
 
     pub fn add_country(&mut self, country: Hoi4Country) {
@@ -44,82 +78,341 @@ impl Hoi4Mod {
         self.units.push(units);
     }
 
-    pub fn export(&self, path: &PathBuf) {
+    pub fn add_character(&mut self, character: Hoi4Character) {
+        self.characters.push(character);
+    }
+
+    /// Adds an idea to the mod's ideas, with the name and description shown in the game.
+    pub fn add_idea_definition(&mut self, idea: Hoi4Idea, name: &str, description: &str) {
+        self.set_localisation("english", &idea.id, name);
+        self.set_localisation("english", &format!("{}_desc", idea.id), description);
+        self.ideas.push(idea);
+    }
+
+    /// Sets the text for a localisation key in one language. Languages without their own text
+    /// for a key use the English text.
+    pub fn set_localisation(&mut self, language: &str, key: &str, value: &str) {
+        self.localisation.set(language, key, value);
+    }
+
+    /// Sets the English names a country is shown with, for all ideologies. Other languages use
+    /// these too unless their own names are set with `set_localisation`.
+    pub fn set_country_names(&mut self, tag: &str, name: &str, adjective: &str, definite_name: &str) {
+        self.set_country_names_for(tag, None, name, adjective, definite_name);
+        for ideology in IDEOLOGIES {
+            self.set_country_names_for(tag, Some(ideology), name, adjective, definite_name);
+        }
+    }
+
+    /// Sets the English names a country is shown with while an ideology is in power, or when no
+    /// name for the ideology is set if `ideology` is None.
+    pub fn set_country_names_for(
+        &mut self, tag: &str, ideology: Option<&str>, name: &str, adjective: &str, definite_name: &str
+    ) {
+        let prefix = match ideology {
+            Some(ideology) => format!("{}_{}", tag, ideology),
+            None => tag.to_string(),
+        };
+
+        self.set_localisation("english", &prefix, name);
+        self.set_localisation("english", &format!("{}_ADJ", prefix), adjective);
+        self.set_localisation("english", &format!("{}_DEF", prefix), definite_name);
+    }
+
+    pub fn localisation(&self) -> &CwLocalisation {
+        &self.localisation
+    }
+
+    /// Adds a file to export with the mod, for content the mod has no specific support for like
+    /// events, decisions or graphics. Fails if another added file has the same path.
+    pub fn add_file(&mut self, file: Hoi4ModFile) -> Result<(), Hoi4ExportError> {
+        let path = try!(normalize_path(&file.path));
+        if self.files.iter().any(|f| f.path.to_lowercase() == path.to_lowercase()) {
+            return Err(Hoi4ExportError::FileConflict(path));
+        }
+
+        self.files.push(Hoi4ModFile { path: path, ..file });
+        Ok(())
+    }
+
+    pub fn files(&self) -> &Vec<Hoi4ModFile> {
+        &self.files
+    }
+
+    /// Exports the mod as a folder in the output path, with a .mod file next to it pointing to
+    /// the folder. The mod is written to a temporary folder first and only replaces the previous
+    /// export once it's complete, which is kept as a backup if backups are enabled.
+    pub fn export(&self, path: &PathBuf) -> Result<(), Hoi4ExportError> {
         info!("Exporting mod to \"{}\"...", path.display());
 
         // Make sure the output dir exists
         if !path.exists() {
-            panic!("Output path does not exist!");
+            return Err(Hoi4ExportError::MissingDirectory(path.display().to_string()));
+        }
+
+        // Only ever replace folders written by an export, a different mod with the same name
+        // shouldn't be deleted
+        let dir = path.join(&self.name);
+        let temp_dir = path.join(format!("{}.tmp", self.name));
+        try!(self.check_exported_dir(&dir));
+        try!(self.check_exported_dir(&temp_dir));
+        try!(self.check_exported_mod_file(path));
+        try!(remove_dir(&temp_dir));
+
+        // Write the mod and make sure it's all there before touching the previous export, the
+        // marker goes first so a failed export can be cleaned up next time
+        let disk = DiskFileSystem::new(temp_dir.clone());
+        try!(write_text(&disk, EXPORT_MARKER, &self.name, false));
+        try!(self.export_content(&disk, ""));
+        if !temp_dir.join("descriptor.mod").is_file() {
+            return Err(Hoi4ExportError::UnwritableFile {
+                file: temp_dir.join("descriptor.mod").display().to_string(), message: "missing after export".into()
+            });
+        }
+
+        // Move the previous export out of the way, either into the backups or to be deleted
+        let old_dir = path.join(format!("{}.old", self.name));
+        let previous = if !dir.exists() {
+            None
+        } else if self.backups != 0 {
+            try!(self.rotate_backups(path));
+            let backup = path.join(format!("{}.backup1", self.name));
+            try!(rename(&dir, &backup));
+            Some(backup)
+        } else {
+            try!(self.check_exported_dir(&old_dir));
+            try!(remove_dir(&old_dir));
+            try!(rename(&dir, &old_dir));
+            Some(old_dir.clone())
+        };
+
+        // If the new export can't be moved in, for example because the launcher has a file open,
+        // put the previous one back so the .mod file doesn't point to nothing
+        if let Err(e) = rename(&temp_dir, &dir) {
+            if let Some(ref previous) = previous {
+                if let Err(restore) = fs::rename(previous, &dir) {
+                    error!("Could not restore the previous export from \"{}\": {}", previous.display(), restore);
+                }
+            }
+            return Err(e);
+        }
+
+        // Only the folder moved there above is ours to delete, with backups it's left alone
+        if previous.as_ref() == Some(&old_dir) {
+            try!(remove_dir(&old_dir));
         }
 
-        // Get the actual directory we export to
-        let mut dir = path.clone();
-        dir.push(&self.name);
+        // The .mod file goes last, so it never points to a folder that isn't complete
+        self.write_mod_file(path, Some(format!("mod/{}", self.name)), None)
+    }
+
+    /// Compares what would be exported with the mod previously exported to the output path,
+    /// without writing anything.
+    pub fn diff(&self, path: &PathBuf) -> Result<Hoi4ExportDiff, Hoi4ExportError> {
+        info!("Comparing mod with export in \"{}\"...", path.display());
 
-        // Delete the old mod directory if it's already there and create a new mod folder to export to
+        let existing = MemoryFileSystem::new();
+        let dir = path.join(&self.name);
         if dir.exists() {
-            warn!("Directory already exists, deleting stale...");
-            fs::remove_dir_all(&dir).unwrap();
+            try!(read_dir_into(&dir, "", &existing));
         }
-        fs::create_dir_all(&dir).unwrap();
 
-        // Create the .mod file
-        self.export_modfile(path);
+        self.diff_files(&existing)
+    }
 
-        // Export the data
-        self.export_countries(&dir);
-        self.export_states(&dir);
-        self.export_units(&dir);
+    /// Compares what would be exported with an archive made by `export_zip` in a directory,
+    /// with everything being added if there isn't one.
+    pub fn diff_zip(&self, path: &PathBuf) -> Result<Hoi4ExportDiff, Hoi4ExportError> {
+        info!("Comparing mod with exported archive in \"{}\"...", path.display());
+
+        let existing = MemoryFileSystem::new();
+        let archive_file = path.join(format!("{}.zip", self.name));
+        if archive_file.exists() {
+            let unreadable = |e: io::Error| Hoi4ExportError::UnreadableFile { file: archive_file.display().to_string(), message: e.to_string() };
+            let archive = try!(ZipFileSystem::open(&archive_file).map_err(&unreadable));
+            for file in archive.paths() {
+                let data = try!(archive.read(&file).map_err(&unreadable));
+                existing.write(&file, &data).unwrap();
+            }
+        }
+
+        self.diff_files(&existing)
     }
 
-    fn export_modfile(&self, path: &PathBuf) {
-        info!("Generating .mod file...");
+    /// Compares what would be exported with the files of a mod, at the root of a file system.
+    pub fn diff_files(&self, existing: &MemoryFileSystem) -> Result<Hoi4ExportDiff, Hoi4ExportError> {
+        let files = MemoryFileSystem::new();
+        try!(self.export_content(&files, ""));
 
-        // Find the actual location of the mod file
-        let mut modfile = path.clone();
-        modfile.push(format!("{}.mod", &self.name));
+        let mut diff = Hoi4ExportDiff::default();
+        for path in files.paths() {
+            let data = files.read(&path).unwrap();
+            match existing.read(&path) {
+                Ok(ref old) if *old == data => {},
+                Ok(old) => {
+                    let changes = if is_script(&path) { script_changes(&old, &data) } else { Vec::new() };
+                    diff.modified.push((path, changes));
+                },
+                Err(_) => diff.added.push(path),
+            }
+        }
+        diff.removed = existing.paths().into_iter()
+            .filter(|p| p != EXPORT_MARKER && !files.is_file(p))
+            .collect();
 
-        // Generate the data
-        let mut dotmod = CwTable::new();
-        dotmod.set("name", (&self.friendly_name).into());
-        dotmod.set("path", format!("mod/{}", self.name).into());
-        dotmod.set("tags", CwValue::Array(self.tags.iter().map(|t| t.into()).collect()));
-        dotmod.set("supported_version", (&self.game_version).into());
+        Ok(diff)
+    }
 
-        // Write the data
-        let dotmod_str = dotmod.serialize();
-        file::write_all_text(modfile, &dotmod_str, false).unwrap();
+    /// Sets how many previous exports are kept as backups next to the mod folder, named like
+    /// "mod_name.backup1" with 1 being the most recent.
+    pub fn set_backups(&mut self, backups: u32) {
+        self.backups = backups;
     }
 
-    fn export_countries(&self, path: &PathBuf) {
-        info!("Exporting countries...");
+    /// Shifts all backups one up to make room for a new one, deleting the oldest.
+    fn rotate_backups(&self, path: &PathBuf) -> Result<(), Hoi4ExportError> {
+        let backup = |i: u32| path.join(format!("{}.backup{}", self.name, i));
 
-        // First write the tag-to-country mapping
-        let mut country_tags = path.clone();
-        country_tags.push("common/country_tags");
-        fs::create_dir_all(&country_tags).unwrap();
-        country_tags.push("countries.txt");
-        file::write_all_text(country_tags, &self.generate_country_tags_table().serialize(), false).unwrap();
+        let oldest = backup(self.backups);
+        try!(self.check_exported_dir(&oldest));
+        try!(remove_dir(&oldest));
 
-        // Get the common and history roots
-        let mut common_file_root = path.clone();
-        common_file_root.push("common/countries");
-        fs::create_dir_all(&common_file_root).unwrap();
+        for i in (1..self.backups).rev() {
+            if backup(i).exists() {
+                try!(rename(&backup(i), &backup(i + 1)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks files were written by exporting this mod, either by the marker written since exports
+    /// have one or by the name in the descriptor for exports from before that.
+    fn is_exported(&self, files: &dyn FileSystem) -> bool {
+        files.is_file(EXPORT_MARKER) || files.read_text("descriptor.mod").ok()
+            .and_then(|text| CwTable::try_parse(&text).ok())
+            .map(|table| ModDescriptor::from_table(&table).name == self.descriptor.name)
+            .unwrap_or(false)
+    }
+
+    /// Checks a directory either doesn't exist or was created by exporting this mod.
+    fn check_exported_dir(&self, dir: &Path) -> Result<(), Hoi4ExportError> {
+        if dir.exists() && !self.is_exported(&DiskFileSystem::new(dir.to_path_buf())) {
+            return Err(Hoi4ExportError::NotExported(dir.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks the .mod file in the output path either doesn't exist or is this mod's, one that
+    /// can't be read or parsed isn't.
+    fn check_exported_mod_file(&self, path: &PathBuf) -> Result<(), Hoi4ExportError> {
+        let file = path.join(format!("{}.mod", self.name));
+        if file.exists() && ModDescriptor::load(&file).map(|d| d.name != self.descriptor.name).unwrap_or(true) {
+            return Err(Hoi4ExportError::NotExported(file.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Writes the .mod file the launcher uses to find a mod, through a temporary file so it's
+    /// either the old or the new one if something goes wrong.
+    fn write_mod_file(
+        &self, path: &PathBuf, mod_path: Option<String>, archive: Option<String>
+    ) -> Result<(), Hoi4ExportError> {
+        let mut descriptor = self.descriptor.clone();
+        descriptor.path = mod_path;
+        descriptor.archive = archive;
+
+        let file_name = format!("{}.mod", self.name);
+        let temp_name = format!("{}.tmp", file_name);
+        try!(write_text(&DiskFileSystem::new(path.clone()), &temp_name, &descriptor.serialize(), false));
+        rename(&path.join(temp_name), &path.join(file_name))
+    }
+
+    /// Writes the mod to a file system laid out like the game's mod directory, the mod's files
+    /// go in a folder named after the mod with the .mod file next to it.
+    pub fn export_to(&self, fs: &dyn FileSystem) -> Result<(), Hoi4ExportError> {
+        try!(self.export_content(fs, &self.name));
+
+        // The launcher also needs a .mod file next to the mod folder, pointing to it
+        let mut descriptor = self.descriptor.clone();
+        descriptor.path = Some(format!("mod/{}", self.name));
+        descriptor.archive = None;
+        write_text(fs, &format!("{}.mod", self.name), &descriptor.serialize(), false)
+    }
+
+    /// Exports the mod as a zip archive in the output path, with a .mod file next to it pointing
+    /// to the archive.
+    pub fn export_zip(&self, path: &PathBuf) -> Result<(), Hoi4ExportError> {
+        info!("Exporting mod archive to \"{}\"...", path.display());
+
+        // Make sure the output dir exists
+        if !path.exists() {
+            return Err(Hoi4ExportError::MissingDirectory(path.display().to_string()));
+        }
+
+        // Like folders, only replace archives and .mod files written by an export
+        let archive_file = path.join(format!("{}.zip", self.name));
+        let temp_file = path.join(format!("{}.zip.tmp", self.name));
+        if archive_file.exists() {
+            let archive = try!(ZipFileSystem::open(&archive_file)
+                .map_err(|_| Hoi4ExportError::NotExported(archive_file.display().to_string())));
+            if !self.is_exported(&archive) {
+                return Err(Hoi4ExportError::NotExported(archive_file.display().to_string()));
+            }
+        }
+        try!(self.check_exported_mod_file(path));
 
-        let mut history_file_root = path.clone();
-        history_file_root.push("history/countries");
-        fs::create_dir_all(&history_file_root).unwrap();
+        // The mod's files go in the root of the archive
+        let files = MemoryFileSystem::new();
+        try!(write_text(&files, EXPORT_MARKER, &self.name, false));
+        try!(self.export_content(&files, ""));
+
+        // Write the archive next to the old one, and only replace it once it's complete
+        try!(archive::write_zip(&files, &temp_file).map_err(|e| Hoi4ExportError::UnwritableFile {
+            file: temp_file.display().to_string(), message: e.to_string()
+        }));
+        try!(rename(&temp_file, &archive_file));
+
+        self.write_mod_file(path, None, Some(format!("mod/{}.zip", self.name)))
+    }
+
+    fn export_content(&self, fs: &dyn FileSystem, root: &str) -> Result<(), Hoi4ExportError> {
+        let mut out = ExportFiles::new(fs, root);
+
+        // The descriptor inside the mod doesn't need a location, it's implied by where it is
+        info!("Generating descriptor...");
+        let mut descriptor = self.descriptor.clone();
+        descriptor.path = None;
+        descriptor.archive = None;
+        try!(out.write_text("descriptor.mod", &descriptor.serialize(), false));
+
+        // Export the data
+        try!(self.export_countries(&mut out));
+        try!(self.export_states(&mut out));
+        try!(self.export_units(&mut out));
+        try!(self.export_characters(&mut out));
+        try!(self.export_ideas(&mut out));
+        try!(self.export_localisation(&mut out));
+        try!(self.export_files(&mut out));
+        out.finish()
+    }
+
+    fn export_countries(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
+        info!("Exporting countries...");
+
+        // First write the tag-to-country mapping
+        try!(out.write_text("common/country_tags/countries.txt", &self.generate_country_tags_table().serialize(), false));
 
         // Write the actual country common and history files
         for country in &self.countries {
-            let mut common_file = common_file_root.clone();
-            common_file.push(format!("{}.txt", country.name()));
-            file::write_all_text(common_file, &country.common_table().serialize(), false).unwrap();
+            let common_file = format!("common/countries/{}.txt", country.name());
+            try!(out.write_text(&common_file, &country.common_table().serialize(), false));
 
-            let mut history_file = history_file_root.clone();
-            history_file.push(format!("{} - {}.txt", country.tag(), country.name()));
-            file::write_all_text(history_file, &country.history_table().serialize(), true).unwrap();
+            let history_file = format!("history/countries/{} - {}.txt", country.tag(), country.name());
+            try!(out.write_text(&history_file, &country.history_table().serialize(), true));
         }
+
+        Ok(())
     }
 
     fn generate_country_tags_table(&self) -> CwTable {
@@ -130,31 +423,326 @@ impl Hoi4Mod {
         table
     }
 
-    fn export_states(&self, path: &PathBuf) {
+    fn export_states(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
         info!("Exporting states...");
 
-        let mut state_file_root = path.clone();
-        state_file_root.push("history/states");
-        fs::create_dir_all(&state_file_root).unwrap();
-
         for state in &self.states {
-            let mut state_file = state_file_root.clone();
-            state_file.push(state.file_name());
-            file::write_all_text(state_file, &state.data().serialize(), false).unwrap();
+            let state_file = format!("history/states/{}", state.file_name());
+            try!(out.write_text(&state_file, &state.data().serialize(), false));
         }
+
+        Ok(())
     }
 
-    fn export_units(&self, path: &PathBuf) {
+    fn export_units(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
         info!("Exporting units...");
 
-        let mut units_file_root = path.clone();
-        units_file_root.push("history/units");
-        fs::create_dir_all(&units_file_root).unwrap();
-
         for units in &self.units {
-            let mut units_file = units_file_root.clone();
-            units_file.push(format!("{}.txt", units.id()));
-            file::write_all_text(units_file, &units.data().serialize(), false).unwrap();
+            let units_file = format!("history/units/{}.txt", units.id());
+            try!(out.write_text(&units_file, &units.data().serialize(), false));
+        }
+
+        Ok(())
+    }
+
+    fn export_characters(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
+        if self.characters.len() == 0 {
+            return Ok(());
+        }
+
+        info!("Exporting characters...");
+
+        // All characters go in a single file, the game doesn't care which file they're in
+        let mut characters = CwTable::new();
+        for character in &self.characters {
+            characters.add(&character.id, character.to_table().into());
         }
+        let mut table = CwTable::new();
+        table.set("characters", characters.into());
+
+        out.write_text(&format!("common/characters/{}.txt", self.name), &table.serialize(), true)
+    }
+
+    fn export_ideas(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
+        if self.ideas.len() == 0 {
+            return Ok(());
+        }
+
+        info!("Exporting ideas...");
+
+        out.write_text(&format!("common/ideas/{}.txt", self.name), &idea::ideas_file(&self.ideas).serialize(), true)
+    }
+
+    fn export_localisation(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
+        if self.localisation.languages().len() == 0 {
+            return Ok(());
+        }
+
+        info!("Exporting localisation...");
+
+        for language in LANGUAGES {
+            // Start with English so there's text for everything, then use what's translated
+            let mut entries: Vec<(String, String)> = Vec::new();
+            for source in &["english", *language] {
+                for (key, value) in self.localisation.entries(source) {
+                    match entries.iter_mut().find(|e| &e.0 == key) {
+                        Some(entry) => entry.1 = value.clone(),
+                        None => entries.push((key.clone(), value.clone())),
+                    }
+                }
+            }
+
+            // The game ignores localisation files without a BOM
+            let file = format!("localisation/{0}/{1}_l_{0}.yml", language, self.name);
+            try!(out.write_text(&file, &localisation::serialize(language, &entries), true));
+        }
+
+        Ok(())
+    }
+
+    fn export_files(&self, out: &mut ExportFiles) -> Result<(), Hoi4ExportError> {
+        if self.files.len() == 0 {
+            return Ok(());
+        }
+
+        info!("Exporting {} other file(s)...", self.files.len());
+
+        for file in &self.files {
+            try!(out.write(&file.path, &file.encode()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The differences between an export and a previously exported mod, by path inside the mod.
+#[derive(Debug, Clone, Default)]
+pub struct Hoi4ExportDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Files that changed, with what changed in them for script files that could be parsed.
+    pub modified: Vec<(String, Vec<CwChange>)>,
+}
+
+impl Hoi4ExportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.len() == 0 && self.removed.len() == 0 && self.modified.len() == 0
+    }
+}
+
+impl fmt::Display for Hoi4ExportDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for path in &self.added {
+            try!(writeln!(f, "Added {}", path));
+        }
+        for path in &self.removed {
+            try!(writeln!(f, "Removed {}", path));
+        }
+        for &(ref path, ref changes) in &self.modified {
+            try!(writeln!(f, "Modified {}", path));
+            for change in changes {
+                try!(writeln!(f, "    {}", change));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What's in a file added to a mod.
+#[derive(Debug, Clone)]
+pub enum Hoi4FileContent {
+    Table(CwTable),
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// How the text in a file is stored. The game reads most script files as UTF-8, some older files
+/// and map files are expected in Windows-1252.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hoi4FileEncoding {
+    Utf8,
+    Utf8Bom,
+    Windows1252,
+}
+
+/// A file exported with a mod, at a path relative to the mod's root.
+#[derive(Debug, Clone)]
+pub struct Hoi4ModFile {
+    pub path: String,
+    pub content: Hoi4FileContent,
+    /// Ignored for binary files.
+    pub encoding: Hoi4FileEncoding,
+}
+
+impl Hoi4ModFile {
+    pub fn table(path: &str, table: CwTable) -> Self {
+        Hoi4ModFile {
+            path: path.into(),
+            content: Hoi4FileContent::Table(table),
+            encoding: Hoi4FileEncoding::Utf8,
+        }
+    }
+
+    pub fn text(path: &str, text: &str) -> Self {
+        Hoi4ModFile {
+            path: path.into(),
+            content: Hoi4FileContent::Text(text.into()),
+            encoding: Hoi4FileEncoding::Utf8,
+        }
+    }
+
+    pub fn binary(path: &str, data: Vec<u8>) -> Self {
+        Hoi4ModFile {
+            path: path.into(),
+            content: Hoi4FileContent::Binary(data),
+            encoding: Hoi4FileEncoding::Utf8,
+        }
+    }
+
+    /// Gets the data written for the file.
+    pub fn encode(&self) -> Vec<u8> {
+        let text = match self.content {
+            Hoi4FileContent::Table(ref table) => table.serialize(),
+            Hoi4FileContent::Text(ref text) => text.clone(),
+            Hoi4FileContent::Binary(ref data) => return data.clone(),
+        };
+
+        match self.encoding {
+            Hoi4FileEncoding::Utf8 => text.into_bytes(),
+            Hoi4FileEncoding::Utf8Bom => format!("\u{feff}{}", text).into_bytes(),
+            Hoi4FileEncoding::Windows1252 => encode_windows_1252(&text),
+        }
+    }
+}
+
+/// The characters in the 0x80 to 0x9F range of Windows-1252, the rest of the upper half is the
+/// same as Unicode. Unused bytes are 0.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0',
+    '\0', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ',
+];
+
+/// Encodes text as Windows-1252, characters it doesn't have become '?'.
+fn encode_windows_1252(text: &str) -> Vec<u8> {
+    text.chars().map(|c| {
+        if (c as u32) < 0x80 || ((c as u32) >= 0xA0 && (c as u32) <= 0xFF) {
+            c as u8
+        } else {
+            WINDOWS_1252_HIGH.iter().position(|&h| h == c).map(|i| 0x80 + i as u8).unwrap_or(b'?')
+        }
+    }).collect()
+}
+
+/// Name of the file put in every exported mod folder, so folders can be recognised as exports
+/// before they're replaced.
+const EXPORT_MARKER: &'static str = ".exported";
+
+/// Extensions of files in the game's script format, which get compared entry by entry.
+const SCRIPT_EXTENSIONS: &'static [&'static str] = &[".txt", ".mod", ".gfx", ".gui"];
+
+fn is_script(path: &str) -> bool {
+    SCRIPT_EXTENSIONS.iter().any(|e| path.ends_with(e))
+}
+
+/// Gets what changed in a script file, nothing if either version can't be parsed.
+fn script_changes(old: &[u8], new: &[u8]) -> Vec<CwChange> {
+    let parse = |data: &[u8]| String::from_utf8(data.to_vec()).ok()
+        .and_then(|text| CwTable::try_parse(text.trim_start_matches('\u{feff}')).ok());
+    match (parse(old), parse(new)) {
+        (Some(old), Some(new)) => diff::diff_tables(&old, &new),
+        _ => Vec::new(),
+    }
+}
+
+/// Reads all files in a directory on disk and its subdirectories into memory.
+fn read_dir_into(dir: &Path, prefix: &str, files: &MemoryFileSystem) -> Result<(), Hoi4ExportError> {
+    let unreadable = |e: io::Error| Hoi4ExportError::UnreadableFile { file: dir.display().to_string(), message: e.to_string() };
+
+    for entry in try!(fs::read_dir(dir).map_err(&unreadable)) {
+        let entry = try!(entry.map_err(&unreadable));
+        let path = vfs::join(prefix, &entry.file_name().to_string_lossy());
+        if try!(entry.file_type().map_err(&unreadable)).is_dir() {
+            try!(read_dir_into(&entry.path(), &path, files));
+        } else {
+            let mut data = Vec::new();
+            try!(fs::File::open(entry.path()).and_then(|mut f| f.read_to_end(&mut data)).map_err(&unreadable));
+            files.write(&path, &data).unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_dir(dir: &Path) -> Result<(), Hoi4ExportError> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    fs::remove_dir_all(dir)
+        .map_err(|e| Hoi4ExportError::UnwritableFile { file: dir.display().to_string(), message: e.to_string() })
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), Hoi4ExportError> {
+    fs::rename(from, to)
+        .map_err(|e| Hoi4ExportError::UnwritableFile { file: to.display().to_string(), message: e.to_string() })
+}
+
+/// Checks a path is relative and stays inside the mod, and makes it use forward slashes.
+fn normalize_path(path: &str) -> Result<String, Hoi4ExportError> {
+    let normalized = path.replace('\\', "/");
+    let parts: Vec<_> = normalized.split('/').collect();
+    if normalized.starts_with('/') || normalized.contains(':') ||
+        parts.iter().any(|p| p.len() == 0 || *p == "." || *p == "..") {
+        return Err(Hoi4ExportError::InvalidPath(path.into()));
+    }
+    Ok(normalized)
+}
+
+fn write_text(fs: &dyn FileSystem, path: &str, text: &str, add_bom: bool) -> Result<(), Hoi4ExportError> {
+    fs.write_text(path, text, add_bom)
+        .map_err(|e| Hoi4ExportError::UnwritableFile { file: path.into(), message: e.to_string() })
+}
+
+/// Writes a mod's files, making sure nothing is written to the same file twice. The game's file
+/// names aren't case sensitive on Windows, so neither is this. Files are only written by `finish`
+/// once all of them are known, so a conflict doesn't leave half an export behind.
+struct ExportFiles<'a> {
+    fs: &'a dyn FileSystem,
+    root: &'a str,
+    paths: HashSet<String>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl<'a> ExportFiles<'a> {
+    fn new(fs: &'a dyn FileSystem, root: &'a str) -> Self {
+        ExportFiles {
+            fs: fs,
+            root: root,
+            paths: HashSet::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, path: &str, data: &[u8]) -> Result<(), Hoi4ExportError> {
+        if !self.paths.insert(path.to_lowercase()) {
+            return Err(Hoi4ExportError::FileConflict(path.into()));
+        }
+
+        self.files.push((path.into(), data.to_vec()));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Hoi4ExportError> {
+        for (path, data) in self.files {
+            let full_path = vfs::join(self.root, &path);
+            try!(self.fs.write(&full_path, &data)
+                .map_err(|e| Hoi4ExportError::UnwritableFile { file: full_path, message: e.to_string() }));
+        }
+        Ok(())
+    }
+
+    fn write_text(&mut self, path: &str, text: &str, add_bom: bool) -> Result<(), Hoi4ExportError> {
+        let bom = if add_bom { "\u{feff}" } else { "" };
+        self.write(path, format!("{}{}", bom, text).as_bytes())
     }
 }
@@ -0,0 +1,298 @@
+//! Typed views of the entries in order of battle files in `history/units`. Every type keeps the
+//! entries it doesn't know about, so converting back to a table doesn't lose anything.
+
+use clausewitz_data::{CwTable, CwValue, CwKeyValue};
+
+/// A unit in a division template's regiment or support grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoi4GridSlot {
+    pub unit: String,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hoi4DivisionTemplate {
+    pub name: String,
+    pub regiments: Vec<Hoi4GridSlot>,
+    pub support: Vec<Hoi4GridSlot>,
+    pub other: CwTable,
+}
+
+impl Hoi4DivisionTemplate {
+    pub fn new(name: &str) -> Self {
+        Hoi4DivisionTemplate {
+            name: name.into(),
+            regiments: Vec::new(),
+            support: Vec::new(),
+            other: CwTable::new(),
+        }
+    }
+
+    pub fn from_table(table: &CwTable) -> Self {
+        Hoi4DivisionTemplate {
+            name: string(table, "name").unwrap_or_default(),
+            regiments: grid(table.get("regiments")),
+            support: grid(table.get("support")),
+            other: other(table, &["name", "regiments", "support"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+        table.values.extend(self.other.values.iter().cloned());
+        table.set("regiments", grid_table(&self.regiments));
+        table.set("support", grid_table(&self.support));
+        table
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hoi4Division {
+    pub name: Option<String>,
+    /// The province the division starts in.
+    pub location: u32,
+    pub template: String,
+    pub experience_factor: Option<f64>,
+    pub equipment_factor: Option<f64>,
+    pub other: CwTable,
+}
+
+impl Hoi4Division {
+    pub fn new(template: &str, location: u32) -> Self {
+        Hoi4Division {
+            name: None,
+            location: location,
+            template: template.into(),
+            experience_factor: None,
+            equipment_factor: None,
+            other: CwTable::new(),
+        }
+    }
+
+    pub fn from_table(table: &CwTable) -> Self {
+        Hoi4Division {
+            name: string(table, "name"),
+            location: number(table, "location").unwrap_or(0),
+            template: string(table, "division_template").unwrap_or_default(),
+            experience_factor: number(table, "start_experience_factor"),
+            equipment_factor: number(table, "start_equipment_factor"),
+            other: other(table, &["name", "location", "division_template", "start_experience_factor",
+                "start_equipment_factor"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        set_optional(&mut table, "name", &self.name);
+        table.set("location", self.location.to_string().into());
+        table.set("division_template", (&self.template).into());
+        set_optional(&mut table, "start_experience_factor", &self.experience_factor);
+        set_optional(&mut table, "start_equipment_factor", &self.equipment_factor);
+        table.values.extend(self.other.values.iter().cloned());
+        table
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hoi4Ship {
+    pub name: String,
+    pub definition: String,
+    /// The equipment and other details of the ship, kept as they are.
+    pub other: CwTable,
+}
+
+impl Hoi4Ship {
+    pub fn from_table(table: &CwTable) -> Self {
+        Hoi4Ship {
+            name: string(table, "name").unwrap_or_default(),
+            definition: string(table, "definition").unwrap_or_default(),
+            other: other(table, &["name", "definition"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+        table.set("definition", (&self.definition).into());
+        table.values.extend(self.other.values.iter().cloned());
+        table
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hoi4TaskForce {
+    pub name: String,
+    pub location: Option<u32>,
+    pub ships: Vec<Hoi4Ship>,
+    pub other: CwTable,
+}
+
+impl Hoi4TaskForce {
+    pub fn from_table(table: &CwTable) -> Self {
+        Hoi4TaskForce {
+            name: string(table, "name").unwrap_or_default(),
+            location: number(table, "location"),
+            ships: tables(table, "ship").into_iter().map(Hoi4Ship::from_table).collect(),
+            other: other(table, &["name", "location", "ship"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+        set_optional(&mut table, "location", &self.location);
+        table.values.extend(self.other.values.iter().cloned());
+        for ship in &self.ships {
+            table.add("ship", ship.to_table().into());
+        }
+        table
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hoi4Fleet {
+    pub name: String,
+    /// The province of the naval base the fleet is stationed at.
+    pub naval_base: Option<u32>,
+    pub task_forces: Vec<Hoi4TaskForce>,
+    pub other: CwTable,
+}
+
+impl Hoi4Fleet {
+    pub fn from_table(table: &CwTable) -> Self {
+        Hoi4Fleet {
+            name: string(table, "name").unwrap_or_default(),
+            naval_base: number(table, "naval_base"),
+            task_forces: tables(table, "task_force").into_iter().map(Hoi4TaskForce::from_table).collect(),
+            other: other(table, &["name", "naval_base", "task_force"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        table.set("name", (&self.name).into());
+        set_optional(&mut table, "naval_base", &self.naval_base);
+        table.values.extend(self.other.values.iter().cloned());
+        for task_force in &self.task_forces {
+            table.add("task_force", task_force.to_table().into());
+        }
+        table
+    }
+}
+
+/// Planes of one type stationed in a state, air wings in OOB files are grouped by state.
+#[derive(Debug, Clone)]
+pub struct Hoi4AirWing {
+    pub state: u32,
+    pub equipment: String,
+    pub owner: Option<String>,
+    pub amount: u32,
+    pub other: CwTable,
+}
+
+impl Hoi4AirWing {
+    pub fn from_table(state: u32, equipment: &str, table: &CwTable) -> Self {
+        Hoi4AirWing {
+            state: state,
+            equipment: equipment.into(),
+            owner: string(table, "owner"),
+            amount: number(table, "amount").unwrap_or(0),
+            other: other(table, &["owner", "amount"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut table = CwTable::new();
+        set_optional(&mut table, "owner", &self.owner);
+        table.set("amount", self.amount.to_string().into());
+        table.values.extend(self.other.values.iter().cloned());
+        table
+    }
+}
+
+/// An `add_equipment_production` in an OOB's `instant_effect`.
+#[derive(Debug, Clone)]
+pub struct Hoi4ProductionLine {
+    pub equipment: String,
+    pub creator: Option<String>,
+    pub requested_factories: u32,
+    pub other: CwTable,
+}
+
+impl Hoi4ProductionLine {
+    pub fn from_table(table: &CwTable) -> Self {
+        let equipment = table.get("equipment").and_then(|v| v.as_table());
+
+        Hoi4ProductionLine {
+            equipment: equipment.and_then(|e| string(e, "type")).unwrap_or_default(),
+            creator: equipment.and_then(|e| string(e, "creator")),
+            requested_factories: number(table, "requested_factories").unwrap_or(0),
+            other: other(table, &["equipment", "requested_factories"]),
+        }
+    }
+
+    pub fn to_table(&self) -> CwTable {
+        let mut equipment = CwTable::new();
+        equipment.set("type", (&self.equipment).into());
+        set_optional(&mut equipment, "creator", &self.creator);
+
+        let mut table = CwTable::new();
+        table.set("equipment", equipment.into());
+        table.set("requested_factories", self.requested_factories.to_string().into());
+        table.values.extend(self.other.values.iter().cloned());
+        table
+    }
+}
+
+fn string(table: &CwTable, key: &str) -> Option<String> {
+    table.get(key).and_then(|v| v.as_string()).cloned()
+}
+
+fn number<T: ::std::str::FromStr>(table: &CwTable, key: &str) -> Option<T> {
+    table.get(key).and_then(|v| v.as_string()).and_then(|v| v.parse().ok())
+}
+
+/// Gets all tables with a key.
+pub fn tables<'a>(table: &'a CwTable, key: &str) -> Vec<&'a CwTable> {
+    table.get_all(key).into_iter().filter_map(|v| v.as_table()).collect()
+}
+
+fn other(table: &CwTable, known: &[&str]) -> CwTable {
+    let mut other = CwTable::new();
+    other.values = table.values.iter().filter(|v| !known.contains(&v.key.as_str())).cloned().collect();
+    other
+}
+
+fn set_optional<T: ToString>(table: &mut CwTable, key: &str, value: &Option<T>) {
+    if let &Some(ref value) = value {
+        table.set(key, value.to_string().into());
+    }
+}
+
+fn grid(value: Option<&CwValue>) -> Vec<Hoi4GridSlot> {
+    let table = match value.and_then(|v| v.as_table()) {
+        Some(table) => table,
+        None => return Vec::new(),
+    };
+
+    table.values.iter()
+        .filter_map(|v| v.value.as_table().map(|t| Hoi4GridSlot {
+            unit: v.key.clone(),
+            x: number(t, "x").unwrap_or(0),
+            y: number(t, "y").unwrap_or(0),
+        }))
+        .collect()
+}
+
+fn grid_table(slots: &[Hoi4GridSlot]) -> CwValue {
+    let mut table = CwTable::new();
+    for slot in slots {
+        let mut position = CwTable::new();
+        position.set("x", slot.x.to_string().into());
+        position.set("y", slot.y.to_string().into());
+        table.values.push(CwKeyValue::new(slot.unit.clone(), position.into()));
+    }
+    table.into()
+}
@@ -0,0 +1,54 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use num_cpus;
+
+/// Maps all items on a pool of worker threads, returning the results in the same order as the
+/// items were given in.
+pub fn map_ordered<T, R, F>(items: Vec<T>, f: F) -> Vec<R>
+    where T: Send + 'static, R: Send + 'static, F: Fn(T) -> R + Send + Sync + 'static
+{
+    let count = items.len();
+    let threads = ::std::cmp::min(num_cpus::get(), count);
+
+    // The workers take items from a shared queue until it's empty
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (sender, receiver) = channel();
+
+    let mut workers = Vec::new();
+    for _ in 0..threads {
+        let queue = queue.clone();
+        let f = f.clone();
+        let sender = sender.clone();
+
+        workers.push(thread::spawn(move || {
+            loop {
+                // Make sure the lock is released before we start working on the item
+                let next = queue.lock().unwrap().next();
+                let (index, item) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                sender.send((index, f(item))).unwrap();
+            }
+        }));
+    }
+    drop(sender);
+
+    // Put the results back in the original order as they come in
+    let mut results: Vec<Option<R>> = (0..count).map(|_| None).collect();
+    for (index, result) in receiver {
+        results[index] = Some(result);
+    }
+
+    // If a worker panicked we'll be missing results, pass the panic along
+    for worker in workers {
+        if let Err(e) = worker.join() {
+            ::std::panic::resume_unwind(e);
+        }
+    }
+
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
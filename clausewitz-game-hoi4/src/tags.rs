@@ -0,0 +1,152 @@
+//! Finding and rewriting references to country tags in history and common data, so data copied
+//! from one country doesn't keep acting on the countries the original was related to.
+
+use clausewitz_data::{CwTable, CwValue};
+
+/// What to do with a reference to a tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hoi4TagAction {
+    Keep,
+    /// Make the reference point to another tag.
+    Remap(String),
+    /// Remove the entire effect or trigger containing the reference.
+    Drop,
+}
+
+/// Keys that reference a tag, either directly as their value or through a `target` or `country`
+/// in their table. `create_faction` and `set_cosmetic_tag` reference names shared between
+/// countries rather than tags, but copying them has the same problems.
+const REFERENCE_KEYS: &'static [&'static str] = &[
+    "owner", "controller", "add_core_of", "remove_core_of", "add_claim_by", "remove_claim_by",
+    "puppet", "release_puppet", "annex_country", "set_autonomy", "give_guarantee", "give_military_access",
+    "create_faction", "add_to_faction", "remove_from_faction", "diplomatic_relation", "declare_war_on",
+    "create_wargoal", "add_opinion_modifier", "reverse_add_opinion_modifier", "set_cosmetic_tag",
+    "inherit_technology", "tag", "original_tag", "country_exists", "has_war_with",
+    "is_puppet_of", "is_subject_of", "is_in_faction_with", "has_guaranteed",
+];
+
+/// Keys in a referencing effect's table that hold the tag it references.
+const TARGET_KEYS: &'static [&'static str] = &["target", "country"];
+
+/// Trigger blocks that look like tags, Andorra's tag is also `AND`.
+const LOGIC_KEYS: &'static [&'static str] = &["AND", "NOT"];
+
+/// Effect name passed to the callback for a `TAG = { ... }` block scoped to a country.
+const SCOPE_EFFECT: &'static str = "scope";
+
+/// Calls the callback with the effect and tag for every tag reference in a table, including in
+/// nested blocks, and applies what it returns.
+pub fn rewrite_tag_references<F>(table: &mut CwTable, f: &mut F)
+    where F: FnMut(&str, &str) -> Hoi4TagAction
+{
+    let mut i = 0;
+    while i < table.values.len() {
+        let action = {
+            let entry = &table.values[i];
+            match reference(&entry.key, &entry.value) {
+                Some((effect, tag)) => f(&effect, &tag),
+                None => Hoi4TagAction::Keep,
+            }
+        };
+
+        match action {
+            Hoi4TagAction::Drop => {
+                table.values.remove(i);
+                continue;
+            },
+            Hoi4TagAction::Remap(tag) => {
+                let entry = &mut table.values[i];
+                remap(&mut entry.key, &mut entry.value, tag);
+            },
+            Hoi4TagAction::Keep => {},
+        }
+
+        // References inside scoped blocks, dated blocks and conditionals
+        if let Some(nested) = table.values[i].value.as_table_mut() {
+            rewrite_tag_references(nested, f);
+        }
+
+        i += 1;
+    }
+}
+
+fn reference(key: &str, value: &CwValue) -> Option<(String, String)> {
+    if is_tag(key) && value.as_table().is_some() {
+        return Some((SCOPE_EFFECT.into(), key.into()));
+    }
+
+    if !REFERENCE_KEYS.contains(&key) {
+        return None;
+    }
+
+    match value {
+        &CwValue::String(ref tag) => Some((key.into(), tag.clone())),
+        &CwValue::Table(ref t) => TARGET_KEYS.iter()
+            .filter_map(|k| t.get(k).and_then(|v| v.as_string()))
+            .next()
+            .map(|tag| (key.into(), tag.clone())),
+        _ => None,
+    }
+}
+
+fn remap(key: &mut String, value: &mut CwValue, tag: String) {
+    if is_tag(key) && value.as_table().is_some() {
+        *key = tag;
+        return;
+    }
+
+    match value {
+        &mut CwValue::String(ref mut old) => *old = tag,
+        &mut CwValue::Table(ref mut t) => {
+            if let Some(target) = TARGET_KEYS.iter().find(|k| t.has_key(k)) {
+                t.set(target, tag.into());
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Checks if a key looks like a country tag, three upper case letters or digits starting with a
+/// letter that isn't a logic block like `AND`.
+pub fn is_tag(key: &str) -> bool {
+    key.len() == 3 && !LOGIC_KEYS.contains(&key) &&
+        key.chars().next().map(|c| c.is_ascii_uppercase()).unwrap_or(false) &&
+        key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use clausewitz_data::CwTable;
+    use super::{Hoi4TagAction, rewrite_tag_references};
+
+    #[test]
+    fn rewrite_references() {
+        let mut history = CwTable::parse("capital = 16 add_to_faction = POL give_guarantee = BEL \
+            diplomatic_relation = { country = ENG relation = military_access active = yes } \
+            1939.1.1 = { puppet = SYR } FRA = { add_claim_by = GER } \
+            if = { limit = { AND = { exists = yes } NOT = { tag = AND } } }");
+
+        rewrite_tag_references(&mut history, &mut |effect, tag| match (effect, tag) {
+            ("add_to_faction", _) | ("give_guarantee", _) => Hoi4TagAction::Drop,
+            (_, "FRA") => Hoi4TagAction::Remap("A01".into()),
+            (_, "ENG") | (_, "SYR") | (_, "AND") => Hoi4TagAction::Remap("B02".into()),
+            (_, "yes") => panic!("\"yes\" is not a tag"),
+            _ => Hoi4TagAction::Keep,
+        });
+
+        assert!(!history.has_key("add_to_faction"));
+        assert!(!history.has_key("give_guarantee"));
+        let relation = history.get("diplomatic_relation").unwrap().as_table().unwrap();
+        assert_eq!(relation.get("country").unwrap().as_string().unwrap(), "B02");
+        let dated = history.get("1939.1.1").unwrap().as_table().unwrap();
+        assert_eq!(dated.get("puppet").unwrap().as_string().unwrap(), "B02");
+        let scope = history.get("A01").unwrap().as_table().unwrap();
+        assert_eq!(scope.get("add_claim_by").unwrap().as_string().unwrap(), "GER");
+
+        // Logic blocks aren't scopes, but tags inside them are still references
+        let limit = history.get("if").unwrap().as_table().unwrap().get("limit").unwrap().as_table().unwrap();
+        assert!(limit.has_key("AND"));
+        let not = limit.get("NOT").unwrap().as_table().unwrap();
+        assert_eq!(not.get("tag").unwrap().as_string().unwrap(), "B02");
+    }
+}
@@ -1,27 +1,52 @@
 use std::path::PathBuf;
 use toml::Parser;
-use clausewitz_data::file;
+use clausewitz_data::{file, vdf};
+use clausewitz_game_hoi4::HOI4_STEAM_APP_ID;
 
 pub struct Config {
     pub mod_name: String,
     pub mod_name_friendly: String,
     pub target_path: PathBuf,
     pub game_path: PathBuf,
+    pub cache_path: Option<PathBuf>,
+    pub export_zip: bool,
+    pub backups: u32,
+    pub dry_run: bool,
+    pub mods: Vec<PathBuf>,
 }
 
 impl Config {
-    pub fn load() -> Self {
+    pub fn load() -> Result<Self, String> {
         info!("Loading config at \"./config/Config.toml\"...");
         let toml = file::read_all_text("./config/Config.toml").unwrap();
         let values = Parser::new(&toml).parse().unwrap();
 
+        // If no game path is given, look it up in the Steam libraries instead
+        let game_path = match values.get("game_path").and_then(|v| v.as_str()) {
+            Some(path) => path.into(),
+            None => {
+                let steam_path = values["steam_path"].as_str().unwrap();
+                info!("Finding game install in Steam libraries at \"{}\"...", steam_path);
+                try!(vdf::find_app_install(steam_path, HOI4_STEAM_APP_ID).ok_or_else(|| format!(
+                    "Hearts of Iron IV is not installed in any Steam library at \"{}\", set game_path in the config instead",
+                    steam_path)))
+            }
+        };
+
         let config = Config {
             mod_name: values["mod_name"].as_str().unwrap().into(),
             mod_name_friendly: values["mod_name_friendly"].as_str().unwrap().into(),
             target_path: values["target_path"].as_str().unwrap().into(),
-            game_path: values["game_path"].as_str().unwrap().into(),
+            game_path: game_path,
+            cache_path: values.get("cache_path").and_then(|v| v.as_str()).map(|v| v.into()),
+            export_zip: values.get("export_zip").and_then(|v| v.as_bool()).unwrap_or(false),
+            dry_run: values.get("dry_run").and_then(|v| v.as_bool()).unwrap_or(false),
+            backups: values.get("backups").and_then(|v| v.as_integer()).unwrap_or(0) as u32,
+            mods: values.get("mods").and_then(|v| v.as_slice())
+                .map(|mods| mods.iter().map(|v| v.as_str().unwrap().into()).collect())
+                .unwrap_or_else(|| Vec::new()),
         };
 
-        config
+        Ok(config)
     }
 }
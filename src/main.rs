@@ -7,10 +7,62 @@ extern crate clausewitz_game_hoi4;
 
 mod config;
 
+use std::process;
+use std::collections::HashSet;
 use rand::{Rng, StdRng};
-use clausewitz_game_hoi4::{CwGameHoi4, Hoi4Mod};
+use clausewitz_data::CwTable;
+use clausewitz_data::cache::ParseCache;
+use clausewitz_game_hoi4::{CwGameHoi4, Hoi4Idea, Hoi4IdAllocator, Hoi4LoadOptions, Hoi4Mod, Hoi4TagAction, ModDescriptor};
 use config::Config;
 
+/// Effects in copied country history that involve other countries and are removed from the copies.
+const DROPPED_EFFECTS: &'static [&'static str] = &[
+    "puppet", "give_guarantee", "give_military_access", "create_faction", "add_to_faction",
+    "diplomatic_relation", "set_cosmetic_tag", "declare_war_on", "create_wargoal", "set_autonomy",
+];
+
+const FRACTURED_STATE_IDEA: &'static str = "shattered_hearts_fractured_state";
+
+/// Creates the ID for a copy of something belonging to another country, these are prefixed with
+/// the tag, like "FRA_1936" or "FRA_albert_lebrun".
+fn copy_id(id: &str, tag: &str) -> String {
+    match id.find('_') {
+        Some(i) => format!("{}{}", tag, &id[i..]),
+        None => format!("{}_{}", tag, id),
+    }
+}
+
+/// Turns a country's display name into a name for its files, like "common/countries/<name>.txt".
+/// Characters that aren't allowed in paths are replaced and the name is numbered if it's already
+/// used, two countries with the same file would conflict.
+fn country_file_name(name: &str, used: &mut HashSet<String>) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .collect();
+    let name = match name.trim_end_matches(|c| c == '.' || c == ' ').trim_start() {
+        "" => "Country",
+        name => name,
+    };
+
+    let mut unique = name.to_string();
+    let mut i = 2;
+    while !used.insert(unique.to_lowercase()) {
+        unique = format!("{} {}", name, i);
+        i += 1;
+    }
+    unique
+}
+
+/// The idea every generated country starts with.
+fn fractured_state_idea() -> Hoi4Idea {
+    let mut idea = Hoi4Idea::new(FRACTURED_STATE_IDEA, "country");
+    idea.allowed = Some(CwTable::parse("always = yes"));
+    idea.removal_cost = Some(-1.0);
+    idea.picture = Some("generic_intel_bonus".into());
+    idea.modifier.set("justify_war_goal_time", "-0.99".into());
+    idea
+}
+
 struct TagGenerator {
     next_tag: i32
 }
@@ -44,7 +96,7 @@ impl TagGenerator {
             }
 
             // Make sure it's not already in use
-            if game.countries().iter().any(|v| v.tag() == &tag) {
+            if game.has_tag(&tag) {
                 continue;
             }
 
@@ -57,56 +109,184 @@ impl TagGenerator {
 fn main() {
     // Load in configuration
     log4rs::init_file("config/Log4rs.toml", Default::default()).unwrap();
-    let config = Config::load();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Could not load config: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Load in the descriptors of the mods we're generating on top of, the mod folder is in the
+    // user directory mod locations are relative to
+    let user_dir = config.target_path.parent().unwrap().to_path_buf();
+    let mut mods = Vec::new();
+    for mod_file in &config.mods {
+        match ModDescriptor::load(user_dir.join(mod_file)) {
+            Ok(descriptor) => mods.push(descriptor),
+            Err(e) => {
+                error!("Could not load mod \"{}\": {}", mod_file.display(), e);
+                process::exit(1);
+            }
+        }
+    }
 
     // Load in the game data
     info!("Loading Hearts of Iron 4 data...");
-    let game = CwGameHoi4::at(&config.game_path);
+    let options = Hoi4LoadOptions {
+        cache: config.cache_path.as_ref().map(|p| ParseCache::new(p.clone()).unwrap()),
+        collect_warnings: true,
+        load_map: false,
+    };
+    let game = match CwGameHoi4::at_with_mods(&config.game_path, &mods, &user_dir, &options) {
+        Ok(game) => game,
+        Err(e) => {
+            error!("Could not load game data: {}", e);
+            process::exit(1);
+        }
+    };
+    if game.warnings().len() != 0 {
+        warn!("Skipped data affected by {} problem(s) while loading", game.warnings().len());
+    }
 
     // Set up the mod file
     let mut modif = Hoi4Mod::new(&config.mod_name, &config.mod_name_friendly, "1.0.1");
     modif.add_tag("Alternative History");
+    modif.set_backups(config.backups);
+    for descriptor in &mods {
+        modif.add_dependency(&descriptor.name);
+    }
+    modif.add_idea_definition(fractured_state_idea(), "Fractured State",
+        "Recently split from a larger nation, we need almost no time to justify wars against our neighbours.");
 
     // Go over all states
     let mut tags = TagGenerator::new();
     let mut rng = StdRng::new().unwrap();
-    let mut next_id = 1000;
+    let mut ids = Hoi4IdAllocator::new(1000);
+    let mut file_names: HashSet<String> = game.countries().iter().map(|c| c.name().to_lowercase()).collect();
     for state in game.states().iter() {
         info!("Generating country for state \"{}\"...", state.name());
+        let (state_id, owner) = match (state.id(), state.owner()) {
+            (Some(id), Some(owner)) => (id, owner),
+            _ => {
+                warn!("Skipping state, it has no ID or owner");
+                continue;
+            }
+        };
+        if let Some(source) = state.history_source("owner") {
+            debug!("Owner {} defined at {}", owner, source);
+        }
 
         // Copy the country with a new name and tag for this state
-        let mut country = game.country_for_tag(state.owner()).unwrap().clone();
+        let mut country = match game.country_for_tag(owner) {
+            Some(country) => country.clone(),
+            None => {
+                warn!("Skipping state, its owner {} was not loaded", owner);
+                continue;
+            }
+        };
+        // There's no adjective for a state's name, so the copy keeps the original's, like "French"
+        let original_tag = country.tag().clone();
+        let name = state.display_name(game.localisation(), "english").unwrap_or(state.name()).clone();
+        let adjective = country.adjective(game.localisation(), "english").cloned().unwrap_or_else(|| name.clone());
+        let definite_name = format!("the State of {}", name);
         country.set_tag(tags.next(&game));
-        country.set_name(state.name().clone());
+        country.set_name(country_file_name(&name, &mut file_names));
+        modif.set_country_names(country.tag(), &name, &adjective, &definite_name);
         country.set_color(rng.gen(), rng.gen(), rng.gen());
-        country.set_capital(state.id().clone());
+        country.set_capital(state_id.clone());
+
+        // The original's diplomacy would have every copy join the same faction and guarantee the
+        // same countries, so drop it and point references to the original at the copy instead
+        let new_tag = country.tag().clone();
+        country.rewrite_tag_references(|effect, tag| {
+            if DROPPED_EFFECTS.contains(&effect) {
+                Hoi4TagAction::Drop
+            } else if tag == original_tag {
+                Hoi4TagAction::Remap(new_tag.clone())
+            } else {
+                Hoi4TagAction::Keep
+            }
+        });
+
+        // Replace IDs in the country and its units so they don't conflict
+        let mut remap = ids.remap();
+        country.replace_ids(&mut remap);
 
-        // Replace IDs in the country so they don't conflict
-        country.replace_ids(|| {next_id+=1; next_id-1});
+        // Give the country copies of the characters it recruits, they can't be shared
+        for character in game.characters_for_country(&country) {
+            let id = copy_id(&character.id, country.tag());
+            country.replace_character(&character.id, &id);
+            modif.add_character(character.clone_as(&id, &mut remap));
+        }
 
-        // Give the country -25% time needed to justify war goals, prevents years of waiting
-        country.add_idea("shattered_hearts_fractured_state".into());
+        // Give the country the idea that cuts the time needed to justify war goals, prevents years of waiting
+        country.add_idea(FRACTURED_STATE_IDEA.into());
 
-        // Copy the state so we can assign ownership
+        // Copy the state so we can assign ownership. Its dated blocks would hand it and its cores
+        // to other countries at later start dates, so only what applies at the start is kept
         let mut modif_state = state.clone();
-        modif_state.set_owner(country.tag().clone());
-        modif_state.set_controller(country.tag().clone());
-        modif_state.add_core(country.tag().clone());
+        if let Err(e) = modif_state.strip_dated_history() {
+            warn!("Could not remove dated history of state \"{}\": {}", state.name(), e);
+        }
+        let assigned = modif_state.set_owner(country.tag().clone())
+            .and_then(|_| modif_state.set_controller(country.tag().clone()))
+            .and_then(|_| modif_state.add_core(country.tag().clone()));
+        if let Err(e) = assigned {
+            warn!("Could not give state \"{}\" to {}: {}", state.name(), country.tag(), e);
+        }
 
-        // Copy the units layout so we can make customize it for this country
-        let mut units = game.units_for_id(country.units()).unwrap().clone();
-        units.set_id(format!("{}_1936", country.tag()));
-        country.set_units(units.id().clone());
+        // Copy the units layouts so we can customize them for this country, including the
+        // separate naval and air ones
+        let provinces = state.provinces().unwrap_or_default();
+        let state_ids: Vec<u32> = state_id.parse().into_iter().collect();
+        let mut all_units = Vec::new();
+        for units in game.units_for_country(&country) {
+            let mut units = units.clone();
+            let id = copy_id(units.id(), country.tag());
+            country.replace_oob(units.id(), &id);
+            units.set_id(id);
+            units.replace_ids(&mut remap);
 
-        // Elimiate all starting units
-        units.clear();
+            // Only keep the units that start in this state
+            units.retain_located_in(&provinces, &state_ids);
+            all_units.push(units);
+        }
 
         // Add the data we need in the mod
         modif.add_country(country);
         modif.add_state(modif_state);
-        modif.add_units(units);
+        for units in all_units {
+            modif.add_units(units);
+        }
+    }
+
+    // Show what would change instead of exporting if asked to
+    if config.dry_run {
+        let diff = if config.export_zip {
+            modif.diff_zip(&config.target_path)
+        } else {
+            modif.diff(&config.target_path)
+        };
+        match diff {
+            Ok(ref diff) if diff.is_empty() => info!("Export would not change anything"),
+            Ok(diff) => info!("Export would make these changes:\n{}", diff),
+            Err(e) => {
+                error!("Could not compare with the exported mod: {}", e);
+                process::exit(1);
+            },
+        }
+        return;
     }
 
     // Export the mod
-    modif.export(&config.target_path);
+    let exported = if config.export_zip {
+        modif.export_zip(&config.target_path)
+    } else {
+        modif.export(&config.target_path)
+    };
+    if let Err(e) = exported {
+        error!("Could not export mod: {}", e);
+        process::exit(1);
+    }
 }